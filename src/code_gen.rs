@@ -1,82 +1,310 @@
+pub mod html;
+pub mod markdown;
+pub mod latex;
+pub mod plain;
+pub mod roff;
+pub mod epub;
+pub mod gemtext;
+
 use std::process;
 
-use crate::parse::Node;
-use crate::lazy_regex;
+use crate::parse::{Align, Node, NodeKind};
 use crate::lang_util::FindRev;
 
-fn node_to_html(node: &Node, base: String) -> String {
-    let parental = |tag, children: &Vec<Box<Node>>, mut base| {
-        base += &format!("<{}>", tag) as &str;
-        for child in children.iter().map(|child| &*child) {
-            base = node_to_html(child, base);
+// an output format that the node tree can be generated into.
+pub trait Backend {
+    // appends the output for `node` and all of its children to `base`.
+    fn node_to_output(&self, node: &Node, base: String) -> String;
+
+    // the text that a protected sequence with `prot_code` turns into.
+    fn deprotect(&self, prot_code: &str) -> String {
+        match prot_code {
+            "LB" => "{",
+            "RB" => "}",
+            "EC" => "]",
+            "P_" => ".",
+            "A_" => "@",
+            
+            // a user should never encode protected sequences manually.
+            // if they do, and they make a mistake, this will quietly remove it.
+            _ => "",
+        }.to_string()
+    }
+
+    fn generate(&self, root: &Node) -> Vec<u8> {
+        if !matches!(root.kind, NodeKind::Root(_)) {
+            error!("tried to generate output from non-root node");
+            process::exit(-1);
         }
-        
-        base += &format!("</{}>", tag) as &str;
-        base
-    };
 
-    let with_data = |tag, data, mut base| {
-        base += &format!("<{}>{}</{}>", tag, data, tag) as &str;
-        base
+        postprocess(self, &self.node_to_output(root, String::new()))
+            .into_bytes()
+    }
+}
+
+pub fn postprocess<B: Backend + ?Sized>(backend: &B, out: &str) -> String {
+    lazy_regex! {
+        PROTECTED_SEQ = r"@#':\[;:[A-Z][A-Z0-9_]\]";
+    }
+
+    let mut out = out.to_string();
+    for mat in PROTECTED_SEQ.find_rev(&out.clone()) {
+        let prot_code = &out[(mat.start() + 7)..(mat.start() + 9)];
+        let replacement = backend.deprotect(prot_code);
+        out.replace_range(mat.range(), &replacement);
+    }
+
+    out
+}
+
+// a piece of inline text, as produced by `.format`, `.link` and `.ref`.
+#[derive(Debug)]
+pub enum Inline {
+    Text(String),
+    
+    // an opening tag, with its link target if it is an `a` tag.
+    Open(String, Option<String>),
+    Close(String),
+}
+
+// decodes a character entity like `&eacute;` or `&#233;`. commonmark uses
+// the html entity table, so pulldown-cmark can look entities up for us.
+pub fn decode_entity(entity: &str) -> Option<String> {
+    use pulldown_cmark::{Event, Parser};
+
+    let text = Parser::new(entity)
+        .filter_map(|event| match event {
+            Event::Text(text) => Some(text.to_string()),
+            _ => None,
+        })
+        .collect::<String>();
+
+    match text == entity {
+        true => None,
+        false => Some(text),
+    }
+}
+
+// splits the text of a node into inline html tags and the text between them,
+// for backends other than html. whitespace is collapsed like html would, and
+// character entities are decoded.
+pub fn inline(text: &str) -> Vec<Inline> {
+    lazy_regex! {
+        TAG = r"<(/?)([A-Za-z][A-Za-z0-9]*)([^>]*)>";
+        HREF = r#"href="([^"]*)""#;
+        WHITESPACE = r"\s+";
+        ENTITY = r"&#?[A-Za-z0-9]+;";
+    }
+
+    let decode = |text: &str| {
+        let text = WHITESPACE.replace_all(text, " ");
+        ENTITY
+            .replace_all(&text, |caps: &regex::Captures| {
+                decode_entity(&caps[0]).unwrap_or_else(|| caps[0].to_string())
+            })
+            .to_string()
     };
 
-    let wrapped = |outer_tag, inner_tag, children: &Vec<Box<Node>>, mut base| {
-        base += &format!("<{}>", outer_tag) as &str;
-        for child in children {
-            base += &format!("<{}>", inner_tag) as &str;
-            base = node_to_html(child, base);
-            base += &format!("</{}>", inner_tag) as &str;
+    let mut out = Vec::new();
+    let mut last_end = 0;
+    for caps in TAG.captures_iter(text) {
+        let mat = caps.get(0).unwrap();
+        if mat.start() > last_end {
+            out.push(Inline::Text(decode(&text[last_end..mat.start()])));
         }
         
-        base += &format!("</{}>", outer_tag) as &str;
-        base
-    };
-    
-    match node {
-        Node::Root(ref children) => parental("html", children, base),
-        Node::Contents(ref children) => parental("body", children, base),
-        Node::Chapter(ref data) => with_data("h1", data, base),
-        Node::Section(ref data) => with_data("h2", data, base),
-        Node::Subsection(ref data) => with_data("h3", data, base),
-        Node::Text(ref data) => with_data("p", data, base),
-        Node::List(ref children) => wrapped("ul", "li", children, base),
-        Node::OrderedList(ref children) => wrapped("ol", "li", children, base),
-        Node::Table(ref children) => parental("table", children, base),
-        Node::Row(ref children) => wrapped("tr", "td", children, base),
+        let tag = caps[2].to_lowercase();
+        if &caps[1] == "/" {
+            out.push(Inline::Close(tag));
+        } else {
+            let href = HREF.captures(&caps[3]).map(|href| href[1].to_string());
+            out.push(Inline::Open(tag, href));
+        }
+
+        last_end = mat.end();
+    }
+
+    if last_end < text.len() {
+        out.push(Inline::Text(decode(&text[last_end..])));
     }
+
+    out
 }
 
-fn postprocess(html: &str) -> String {
+// escapes the characters of `text` with `escape_ch`, leaving protected
+// sequences intact so that they can still be deprotected afterwards.
+pub fn escape<F>(text: &str, escape_ch: F) -> String
+where
+    F: Fn(char) -> Option<&'static str>,
+{
     lazy_regex! {
         PROTECTED_SEQ = r"@#':\[;:[A-Z][A-Z0-9_]\]";
     }
 
-    let mut html = html.to_string();
-    for mat in PROTECTED_SEQ.find_rev(&html.clone()) {
-        let prot_code = html[(mat.start() + 7)..(mat.start() + 9)].to_string();
-        let replacement = match prot_code.as_ref() {
-            "LB" => "{",
-            "RB" => "}",
-            "EC" => "]",
-            "P_" => ".",
-            "A_" => "@",
-            
-            // a user should never encode protected sequences manually.
-            // if they do, and they make a mistake, this will quietly remove it.
-            _ => "",
-        };
+    let escape_all = |text: &str| {
+        text.chars().fold(String::new(), |mut out, ch| {
+            match escape_ch(ch) {
+                Some(escaped) => out += escaped,
+                None => out.push(ch),
+            }
 
-        html.replace_range(mat.range(), replacement);
+            out
+        })
+    };
+    
+    let mut out = String::new();
+    let mut last_end = 0;
+    for mat in PROTECTED_SEQ.find_iter(text) {
+        out += &escape_all(&text[last_end..mat.start()]);
+        out += mat.as_str();
+        last_end = mat.end();
     }
 
-    html
+    out + &escape_all(&text[last_end..])
 }
 
-pub fn generate_html(root: &Node) -> String {
-    if !matches!(root, Node::Root(_)) {
-        error!("tried to generate html from non-root node");
-        process::exit(-1);
+// a table cell placed on the grid of its table.
+pub struct GridCell<'a> {
+    pub nodes: Vec<&'a Node>,
+    pub col: usize,
+    pub colspan: usize,
+    pub rowspan: usize,
+
+    // whether the cell is in a header row or is a header cell itself.
+    pub is_header: bool,
+}
+
+pub struct GridRow<'a> {
+    pub cells: Vec<GridCell<'a>>,
+    pub is_header: bool,
+}
+
+pub struct Grid<'a> {
+    pub caption: Option<&'a str>,
+    pub rows: Vec<GridRow<'a>>,
+    pub width: usize,
+    pub align: &'a [Align],
+}
+
+impl Grid<'_> {
+    // columns without an alignment of their own are left aligned.
+    pub fn align(&self, col: usize) -> Align {
+        self.align.get(col).copied().unwrap_or(Align::Left)
+    }
+}
+
+// lays out the children of a table on a grid of columns. cells covered by a
+// row span from a previous row are left out in vvsml, like in html, so the
+// cells of a row do not always start at the first column or follow each other
+// without gaps.
+pub fn table_grid<'a>(
+    children: &'a [Box<Node>],
+    align: &'a [Align],
+) -> Grid<'a> {
+    let mut grid = Grid {
+        caption: None,
+        rows: Vec::new(),
+        width: 0,
+        align,
+    };
+
+    // the number of rows that each column is still covered for.
+    let mut covered: Vec<usize> = Vec::new();
+    for child in children {
+        let (cells, is_header) = match child.kind {
+            NodeKind::Caption(ref data) => {
+                grid.caption = Some(data);
+                continue;
+            }
+            NodeKind::Row(ref cells) => (cells, false),
+            NodeKind::HeaderRow(ref cells) => (cells, true),
+            _ => continue,
+        };
+
+        let active = covered.clone();
+        let is_covered = |col| active.get(col).is_some_and(|rows| *rows > 0);
+        for rows in covered.iter_mut().filter(|rows| **rows > 0) {
+            *rows -= 1;
+        }
+        
+        let mut row = GridRow {
+            cells: Vec::new(),
+            is_header,
+        };
+
+        let mut col = 0;
+        for cell in cells {
+            while is_covered(col) {
+                col += 1;
+            }
+
+            let (nodes, colspan, rowspan, header) = match cell.kind {
+                NodeKind::Cell(ref children, colspan, rowspan, header) => {
+                    let nodes = children.iter().map(|child| &**child);
+                    (nodes.collect(), colspan, rowspan, header)
+                }
+                _ => (vec![&**cell], 1, 1, false),
+            };
+
+            if covered.len() < col + colspan {
+                covered.resize(col + colspan, 0);
+            }
+
+            for rows in &mut covered[col..(col + colspan)] {
+                *rows = rowspan - 1;
+            }
+
+            row.cells.push(GridCell {
+                nodes,
+                col,
+                colspan,
+                rowspan,
+                is_header: is_header || header,
+            });
+            
+            col += colspan;
+        }
+
+        let covered_width = active
+            .iter()
+            .rposition(|rows| *rows > 0)
+            .map_or(0, |col| col + 1);
+
+        grid.width = grid.width.max(col).max(covered_width);
+        grid.rows.push(row);
     }
 
-    postprocess(&node_to_html(root, String::new()))
+    grid
+}
+
+// command line options that affect code generation.
+pub struct Options {
+    // the column to wrap text at, for backends that wrap text.
+    pub width: usize,
+
+    // the document title, for backends that need one.
+    pub title: String,
+
+    // whether to pretty print output, for backends that support it.
+    pub pretty: bool,
+}
+
+// looks up the backend for an output format given with `--format`.
+pub fn backend(format: &str, opts: &Options) -> Option<Box<dyn Backend>> {
+    match format {
+        "html" => Some(Box::new(html::Html { pretty: opts.pretty })),
+        "markdown" | "md" => Some(Box::new(markdown::Markdown)),
+        "latex" | "tex" => Some(Box::new(latex::Latex)),
+        "plain" | "txt" => Some(Box::new(plain::Plain { width: opts.width })),
+        "roff" | "man" => Some(Box::new(roff::Roff {
+            title: opts.title.clone(),
+        })),
+        "gemtext" | "gmi" => Some(Box::new(gemtext::Gemtext {
+            width: opts.width,
+        })),
+        "epub" => Some(Box::new(epub::Epub {
+            title: opts.title.clone(),
+        })),
+        _ => None,
+    }
 }
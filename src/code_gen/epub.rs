@@ -0,0 +1,403 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use zip::write::{FileOptions, ZipWriter};
+use zip::CompressionMethod;
+
+use crate::parse::{Node, NodeKind};
+use crate::code_gen::{self, html, Backend, Inline};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0"
+    xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf"
+        media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn escape(text: &str) -> String {
+    code_gen::escape(text, |ch| match ch {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        _ => None,
+    })
+}
+
+// the text of a heading without any of its inline markup, for titles and the
+// navigation document.
+fn plain_text(text: &str) -> String {
+    let pieces = code_gen::inline(text).into_iter().filter_map(|piece| {
+        match piece {
+            Inline::Text(text) => Some(text),
+            _ => None,
+        }
+    });
+
+    escape(pieces.collect::<String>().trim())
+}
+
+// a chapter and everything up to the next chapter, written to one content
+// document. anything before the first chapter gets a document of its own.
+struct Document {
+    file: String,
+    nodes: Vec<Node>,
+}
+
+fn split(root: &Node) -> Vec<Document> {
+    let mut chapters: Vec<Vec<Node>> = Vec::new();
+    let contents = root.kind.children().iter().flat_map(|contents| {
+        contents.kind.children().iter()
+    });
+
+    for node in contents {
+        let is_chapter = matches!(node.kind, NodeKind::Chapter(..));
+        if is_chapter || chapters.is_empty() {
+            chapters.push(Vec::new());
+        }
+
+        chapters.last_mut().unwrap().push((**node).clone());
+    }
+
+    chapters
+        .into_iter()
+        .enumerate()
+        .map(|(i, nodes)| Document {
+            file: format!("chapter-{}.xhtml", i + 1),
+            nodes,
+        })
+        .collect()
+}
+
+// an entry of the table of contents, with the depth of its heading.
+struct NavEntry {
+    level: usize,
+    title: String,
+    href: String,
+}
+
+// headings need an id to be linked to from the navigation document, so those
+// without a label or id are given one.
+fn nav_entries(docs: &mut Vec<Document>) -> Vec<NavEntry> {
+    let mut entries = Vec::new();
+    for doc in docs {
+        for (i, node) in doc.nodes.iter_mut().enumerate() {
+            let (level, data, label) = match node.kind {
+                NodeKind::Chapter(ref data, ref label) => (1, data, label),
+                NodeKind::Section(ref data, ref label) => (2, data, label),
+                NodeKind::Subsection(ref data, ref label) => (3, data, label),
+                _ => continue,
+            };
+
+            let title = plain_text(data);
+            let id = node
+                .attrs
+                .id
+                .clone()
+                .or(label.clone())
+                .unwrap_or_else(|| format!("heading-{}", i + 1));
+
+            node.attrs.id = Some(id.clone());
+            entries.push(NavEntry {
+                level,
+                title,
+                href: format!("{}#{}", doc.file, id),
+            });
+        }
+    }
+
+    entries
+}
+
+// entries are nested under the closest entry before them with a lower level.
+fn nav_list(entries: &[NavEntry], indent: usize) -> String {
+    let pad = "  ".repeat(indent);
+    let mut out = format!("{}<ol>\n", pad);
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let end = entries[(i + 1)..]
+            .iter()
+            .position(|next| next.level <= entry.level)
+            .map_or(entries.len(), |pos| i + 1 + pos);
+
+        out += &format!(
+            "{}  <li><a href=\"{}\">{}</a>",
+            pad,
+            entry.href,
+            entry.title,
+        );
+
+        if end > i + 1 {
+            out += "\n";
+            out += &nav_list(&entries[(i + 1)..end], indent + 2);
+            out += &format!("{}  ", pad);
+        }
+
+        out += "</li>\n";
+        i = end;
+    }
+
+    out + &pad + "</ol>\n"
+}
+
+// xhtml only knows the entities predefined by xml, and does not allow an `&`
+// that does not start an entity, which html quietly accepts. other named
+// entities are written as character references.
+fn xml_entities(html: &str) -> String {
+    lazy_regex! {
+        ENTITY = r"&(#?[A-Za-z0-9]+;)?";
+    }
+
+    let xml_entity = |name: &str| {
+        name.starts_with('#')
+            || matches!(name, "amp;" | "lt;" | "gt;" | "quot;" | "apos;")
+    };
+
+    ENTITY
+        .replace_all(html, |caps: &regex::Captures| match caps.get(1) {
+            Some(name) if xml_entity(name.as_str()) => caps[0].to_string(),
+            Some(_) => match code_gen::decode_entity(&caps[0]) {
+                Some(text) => text
+                    .chars()
+                    .map(|ch| format!("&#{};", ch as u32))
+                    .collect(),
+                None => format!("&amp;{}", &caps[0][1..]),
+            },
+            None => String::from("&amp;"),
+        })
+        .to_string()
+}
+
+fn xhtml(title: &str, body: &str, extra_ns: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\"{}>\n\
+         \x20 <head>\n\
+         \x20   <title>{}</title>\n\
+         \x20 </head>\n\
+         \x20 <body>\n\
+         {}\
+         \x20 </body>\n\
+         </html>\n",
+        extra_ns,
+        title,
+        body,
+    )
+}
+
+// the current time in the form required by `dcterms:modified`.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64);
+
+    // converts days since the epoch into a civil date.
+    let days = secs.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+
+    let day_of_year = day_of_era
+        - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+
+    let month_idx = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_idx + 2) / 5 + 1;
+    let month = if month_idx < 10 { month_idx + 3 } else { month_idx - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+    )
+}
+
+fn package(title: &str, docs: &[Document], identifier: &str) -> String {
+    let manifest = docs
+        .iter()
+        .enumerate()
+        .map(|(i, doc)| format!(
+            "    <item id=\"chapter-{}\" href=\"{}\" \
+             media-type=\"application/xhtml+xml\"/>\n",
+            i + 1,
+            doc.file,
+        ))
+        .collect::<String>();
+
+    let spine = (0..docs.len())
+        .map(|i| format!("    <itemref idref=\"chapter-{}\"/>\n", i + 1))
+        .collect::<String>();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+         unique-identifier=\"book-id\">\n\
+         \x20 <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+         \x20   <dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+         \x20   <dc:title>{}</dc:title>\n\
+         \x20   <dc:language>en</dc:language>\n\
+         \x20   <meta property=\"dcterms:modified\">{}</meta>\n\
+         \x20 </metadata>\n\
+         \x20 <manifest>\n\
+         \x20   <item id=\"nav\" href=\"nav.xhtml\" \
+         media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         {}\
+         \x20 </manifest>\n\
+         \x20 <spine>\n\
+         {}\
+         \x20 </spine>\n\
+         </package>\n",
+        identifier,
+        title,
+        timestamp(),
+        manifest,
+        spine,
+    )
+}
+
+pub struct Epub {
+    pub title: String,
+}
+
+impl Epub {
+    // the content documents, with references to labels in other documents
+    // pointing into those documents.
+    fn documents(&self, docs: &[Document]) -> Vec<String> {
+        lazy_regex! {
+            REF = r##"href="#([^"]*)""##;
+        }
+
+        let mut files = HashMap::new();
+        let mut stack = Vec::new();
+        for doc in docs {
+            stack.extend(doc.nodes.iter());
+            while let Some(node) = stack.pop() {
+                let id = node.attrs.id.as_ref().or(node.kind.label());
+                if let Some(id) = id {
+                    files.insert(id.clone(), doc.file.clone());
+                }
+
+                stack.extend(node.kind.children().iter().map(|child| &**child));
+            }
+        }
+
+        let mut out = Vec::new();
+        for doc in docs {
+            let body = doc.nodes.iter().fold(String::new(), |base, node| {
+                html::node_to_html(node, base, Some(2))
+            });
+
+            let body = REF.replace_all(&body, |caps: &regex::Captures| {
+                match files.get(&caps[1]) {
+                    Some(file) if *file != doc.file => {
+                        format!("href=\"{}#{}\"", file, &caps[1])
+                    }
+                    _ => caps[0].to_string(),
+                }
+            });
+
+            let body = xml_entities(&body);
+
+            let title = doc.nodes.iter().find_map(|node| match node.kind {
+                NodeKind::Chapter(ref data, _) => Some(plain_text(data)),
+                _ => None,
+            });
+
+            let title = title.unwrap_or_else(|| escape(&self.title));
+            let body = body.trim_start_matches('\n').to_string() + "\n";
+            out.push(code_gen::postprocess(self, &xhtml(&title, &body, "")));
+        }
+
+        out
+    }
+}
+
+impl Backend for Epub {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        html::node_to_html(node, base, None)
+    }
+
+    // a zip archive with the mimetype first and uncompressed, then the
+    // container, package and navigation documents, then the content.
+    fn generate(&self, root: &Node) -> Vec<u8> {
+        if !matches!(root.kind, NodeKind::Root(_)) {
+            error!("tried to generate output from non-root node");
+            process::exit(-1);
+        }
+
+        let mut docs = split(root);
+        let mut entries = nav_entries(&mut docs);
+        let title = escape(&self.title);
+
+        // the table of contents may not be empty.
+        if entries.is_empty() {
+            entries.extend(docs.iter().map(|doc| NavEntry {
+                level: 1,
+                title: title.clone(),
+                href: doc.file.clone(),
+            }));
+        }
+
+        let contents = self.documents(&docs);
+        let mut hasher = DefaultHasher::new();
+        (&self.title, &contents).hash(&mut hasher);
+        let identifier = format!("urn:vvsml:{:016x}", hasher.finish());
+
+        let nav = xhtml(
+            &title,
+            &format!(
+                "    <nav epub:type=\"toc\">\n{}    </nav>\n",
+                nav_list(&entries, 3),
+            ),
+            " xmlns:epub=\"http://www.idpf.org/2007/ops\"",
+        );
+
+        let mut files = vec![
+            (String::from("META-INF/container.xml"), CONTAINER.to_string()),
+            (
+                String::from("OEBPS/content.opf"),
+                package(&title, &docs, &identifier),
+            ),
+            (String::from("OEBPS/nav.xhtml"), nav),
+        ];
+
+        for (doc, content) in docs.iter().zip(contents) {
+            files.push((format!("OEBPS/{}", doc.file), content));
+        }
+
+        let write = || -> zip::result::ZipResult<Vec<u8>> {
+            let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+            let stored = FileOptions::default()
+                .compression_method(CompressionMethod::Stored);
+
+            zip.start_file("mimetype", stored)?;
+            zip.write_all(b"application/epub+zip")?;
+            for (name, content) in &files {
+                zip.start_file(name, FileOptions::default())?;
+                zip.write_all(content.as_bytes())?;
+            }
+
+            Ok(zip.finish()?.into_inner())
+        };
+
+        write().unwrap_or_else(|e| {
+            error!(format!("unable to create epub archive: {}", e));
+            process::exit(-1);
+        })
+    }
+}
@@ -0,0 +1,190 @@
+use crate::parse::{Align, Node, NodeKind};
+use crate::code_gen::{self, plain::Plain, Backend, Inline};
+
+// the text of a line without markup, and the targets and text of the links
+// in it. gemtext links can only be written on lines of their own.
+fn inline_to_gem(text: &str) -> (String, Vec<(String, String)>) {
+    let mut out = String::new();
+    let mut links = Vec::new();
+    let mut open_links: Vec<(String, String)> = Vec::new();
+    let mut closers = Vec::new();
+    for piece in code_gen::inline(text) {
+        match piece {
+            Inline::Text(text) => {
+                out += &text;
+                for (_, link_text) in open_links.iter_mut() {
+                    link_text.push_str(&text);
+                }
+            }
+            Inline::Open(tag, href) => {
+                match tag.as_str() {
+                    "sub" => out.push('_'),
+                    "sup" => out.push('^'),
+                    _ => {}
+                }
+
+                // references to labels have nowhere to link to.
+                let href = href.filter(|href| {
+                    tag == "a" && !href.starts_with('#')
+                });
+
+                closers.push(href.is_some());
+                if let Some(href) = href {
+                    open_links.push((href, String::new()));
+                }
+            }
+            Inline::Close(_) => if closers.pop() == Some(true) {
+                let (href, link_text) = open_links.pop().unwrap();
+                links.push((href, link_text.trim().to_string()));
+            }
+        }
+    }
+
+    (out.trim().to_string(), links)
+}
+
+// a text line starting like a heading, list item, quote, link or
+// preformatting toggle would be read as one.
+fn text_line(text: &str) -> String {
+    if text.starts_with(['#', '*', '>']) || text.starts_with("=>")
+        || text.starts_with("```")
+    {
+        format!(" {}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn line_with_links(prefix: &str, text: &str) -> Vec<String> {
+    let (line, links) = inline_to_gem(text);
+    let link_lines = links
+        .iter()
+        .map(|(href, link_text)| format!("=> {} {}", href, link_text));
+
+    // a paragraph that is only a link is replaced by the link line.
+    if let [(_, link_text)] = links.as_slice() {
+        if prefix.is_empty() && *link_text == line {
+            return link_lines.collect();
+        }
+    }
+
+    let line = match prefix {
+        "" => text_line(&line),
+        _ => format!("{}{}", prefix, line),
+    };
+
+    let mut lines = vec![line];
+    lines.extend(link_lines);
+    lines
+}
+
+pub struct Gemtext {
+    // the width of preformatted tables.
+    pub width: usize,
+}
+
+impl Gemtext {
+    fn blocks(&self, children: &[Box<Node>]) -> Vec<String> {
+        children
+            .iter()
+            .map(|child| self.node_lines(child).join("\n"))
+            .filter(|block| !block.is_empty())
+            .collect()
+    }
+
+    // gemtext lists cannot be nested, so the items of nested lists follow the
+    // item they are in. ordered lists keep their numbers in the item text.
+    fn list_lines(
+        &self,
+        children: &[Box<Node>],
+        marker: &dyn Fn(usize) -> String,
+        lines: &mut Vec<String>,
+    ) {
+        for (i, child) in children.iter().enumerate() {
+            let prefix = format!("* {}", marker(i));
+            let nodes = match child.kind {
+                NodeKind::Item(ref children, _) => children.as_slice(),
+                _ => std::slice::from_ref(child),
+            };
+
+            for node in nodes {
+                self.item_lines(node, &prefix, lines);
+            }
+        }
+    }
+
+    fn item_lines(&self, node: &Node, prefix: &str, lines: &mut Vec<String>) {
+        match node.kind {
+            NodeKind::Chapter(ref data, _)
+            | NodeKind::Section(ref data, _)
+            | NodeKind::Subsection(ref data, _)
+            | NodeKind::Text(ref data, _) => {
+                lines.extend(line_with_links(prefix, data));
+            }
+            NodeKind::List(ref children) => {
+                self.list_lines(children, &|_| String::new(), lines);
+            }
+            NodeKind::OrderedList(ref children, ref numbering) => {
+                self.list_lines(children, &|i| {
+                    let num = numbering.number(i, children.len());
+                    format!("{}. ", numbering.style.format(num))
+                }, lines);
+            }
+            _ => lines.extend(self.node_lines(node)),
+        }
+    }
+
+    fn table(&self, children: &[Box<Node>], align: &[Align]) -> Vec<String> {
+        let grid = code_gen::table_grid(children, align);
+        let plain = Plain { width: self.width };
+
+        // the caption is the alt text of the preformatted block.
+        let alt = grid.caption.map_or(String::new(), |caption| {
+            inline_to_gem(caption).0
+        });
+
+        let mut lines = vec![format!("```{}", alt)];
+        lines.extend(plain.grid_lines(&grid, self.width));
+        lines.push(String::from("```"));
+        lines
+    }
+
+    fn node_lines(&self, node: &Node) -> Vec<String> {
+        match node.kind {
+            NodeKind::Root(ref children) | NodeKind::Contents(ref children) => {
+                vec![self.blocks(children).join("\n\n")]
+            }
+            NodeKind::Chapter(ref data, _) => line_with_links("# ", data),
+            NodeKind::Section(ref data, _) => line_with_links("## ", data),
+            NodeKind::Subsection(ref data, _) => line_with_links("### ", data),
+            NodeKind::Text(ref data, _) | NodeKind::Caption(ref data) => {
+                line_with_links("", data)
+            }
+            NodeKind::List(_) | NodeKind::OrderedList(..) => {
+                let mut lines = Vec::new();
+                self.item_lines(node, "", &mut lines);
+                lines
+            }
+            NodeKind::Item(ref children, _)
+            | NodeKind::Cell(ref children, ..) => {
+                vec![self.blocks(children).join("\n\n")]
+            }
+            NodeKind::Table(ref children, _, ref align) => {
+                self.table(children, align)
+            }
+            NodeKind::Row(ref children) | NodeKind::HeaderRow(ref children) => {
+                let cells = children.iter().map(|child| {
+                    self.node_lines(child).join(" ")
+                });
+
+                vec![cells.collect::<Vec<_>>().join(" | ")]
+            }
+        }
+    }
+}
+
+impl Backend for Gemtext {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        base + &self.node_lines(node).join("\n") + "\n"
+    }
+}
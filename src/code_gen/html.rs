@@ -0,0 +1,279 @@
+use crate::parse::{
+    Align,
+    Node,
+    NodeKind,
+    Attributes,
+    Numbering,
+    NumberStyle,
+};
+use crate::code_gen::{self, Backend, Grid, GridRow};
+
+// a label doubles as the id of a node.
+fn attrs_html(attrs: &Attributes, label: &Option<String>) -> String {
+    let mut out = String::new();
+    if let Some(id) = attrs.id.as_ref().or(label.as_ref()) {
+        out += &format!(" id=\"{}\"", id) as &str;
+    }
+
+    if !attrs.classes.is_empty() {
+        out += &format!(" class=\"{}\"", attrs.classes.join(" ")) as &str;
+    }
+
+    for (key, value) in &attrs.data {
+        out += &format!(" {}=\"{}\"", key, value) as &str;
+    }
+
+    out
+}
+
+// with pretty printing, every block element starts on a line of its own,
+// indented by its depth. the contents of paragraphs and headings are written
+// as they are, so inline markup and `<pre>` blocks in them stay intact.
+fn indent(depth: Option<usize>) -> String {
+    match depth {
+        Some(depth) => format!("\n{}", "  ".repeat(depth)),
+        None => String::new(),
+    }
+}
+
+fn cell_to_html(
+    tag: &str,
+    attrs: &str,
+    children: &[Box<Node>],
+    colspan: usize,
+    rowspan: usize,
+    mut base: String,
+    depth: Option<usize>,
+) -> String {
+    base += &format!("{}<{}{}", indent(depth), tag, attrs) as &str;
+    if colspan > 1 {
+        base += &format!(" colspan=\"{}\"", colspan) as &str;
+    }
+
+    if rowspan > 1 {
+        base += &format!(" rowspan=\"{}\"", rowspan) as &str;
+    }
+
+    base += ">";
+    for child in children {
+        base = node_to_html(child, base, depth.map(|depth| depth + 1));
+    }
+
+    base += &format!("{}</{}>", indent(depth), tag) as &str;
+    base
+}
+
+// the cells of a row, with each cell aligned like the column it starts in.
+fn row_to_html(
+    row: &Node,
+    grid_row: &GridRow,
+    grid: &Grid,
+    mut base: String,
+    depth: Option<usize>,
+) -> String {
+    let inner = depth.map(|depth| depth + 1);
+    let tr_attrs = attrs_html(&row.attrs, &None);
+    base += &format!("{}<tr{}>", indent(depth), tr_attrs) as &str;
+    for (child, cell) in row.kind.children().iter().zip(&grid_row.cells) {
+        let tag = if cell.is_header { "th" } else { "td" };
+
+        // the attributes of anything other than a cell belong to the node
+        // wrapped in the cell.
+        let (mut attrs, children) = match child.kind {
+            NodeKind::Cell(ref children, ..) => {
+                (attrs_html(&child.attrs, &None), children.as_slice())
+            }
+            _ => (String::new(), std::slice::from_ref(child)),
+        };
+
+        attrs += match grid.align(cell.col) {
+            Align::Left => "",
+            Align::Right | Align::Numeric => " style=\"text-align: right\"",
+            Align::Center => " style=\"text-align: center\"",
+        };
+
+        base = cell_to_html(
+            tag,
+            &attrs,
+            children,
+            cell.colspan,
+            cell.rowspan,
+            base,
+            inner,
+        );
+    }
+
+    base += &format!("{}</tr>", indent(depth)) as &str;
+    base
+}
+
+pub fn numbering_attrs(numbering: &Numbering) -> String {
+    let mut attrs = String::new();
+    if let Some(start) = numbering.start {
+        attrs += &format!(" start=\"{}\"", start) as &str;
+    }
+
+    attrs += match numbering.style {
+        NumberStyle::Decimal => "",
+        NumberStyle::LowerAlpha => " type=\"a\"",
+        NumberStyle::UpperAlpha => " type=\"A\"",
+        NumberStyle::LowerRoman => " type=\"i\"",
+        NumberStyle::UpperRoman => " type=\"I\"",
+    };
+
+    // written out in full so that the markup is also valid xhtml.
+    if numbering.reversed {
+        attrs += " reversed=\"reversed\"";
+    }
+
+    attrs
+}
+
+// `depth` is the depth of `node` when pretty printing, or `None` to write
+// everything on one line.
+pub fn node_to_html(
+    node: &Node,
+    base: String,
+    depth: Option<usize>,
+) -> String {
+    let attrs = |label| attrs_html(&node.attrs, label);
+    let inner = depth.map(|depth| depth + 1);
+    
+    let parental = |tag, attrs: &str, children: &Vec<Box<Node>>, mut base| {
+        base += &format!("{}<{}{}>", indent(depth), tag, attrs) as &str;
+        for child in children.iter().map(|child| &*child) {
+            base = node_to_html(child, base, inner);
+        }
+        
+        base += &format!("{}</{}>", indent(depth), tag) as &str;
+        base
+    };
+
+    let with_data = |tag, attrs: &str, data, mut base| {
+        base += &indent(depth) as &str;
+        base += &format!("<{}{}>{}</{}>", tag, attrs, data, tag) as &str;
+        base
+    };
+
+    let wrapped = |
+        outer_tag,
+        outer_attrs: &str,
+        inner_tag,
+        children: &Vec<Box<Node>>,
+        mut base,
+    | {
+        base += &indent(depth) as &str;
+        base += &format!("<{}{}>", outer_tag, outer_attrs) as &str;
+        for child in children {
+            // explicit cells and list items are not wrapped again.
+            match child.kind {
+                NodeKind::Cell(ref cell_children, colspan, rowspan, header) => {
+                    base = cell_to_html(
+                        if header { "th" } else { inner_tag },
+                        &attrs_html(&child.attrs, &None),
+                        cell_children,
+                        colspan,
+                        rowspan,
+                        base,
+                        inner,
+                    );
+                }
+                NodeKind::Item(..) => base = node_to_html(child, base, inner),
+                _ => {
+                    let innermost = inner.map(|depth| depth + 1);
+                    let line = indent(inner);
+                    base += &format!("{}<{}>", line, inner_tag) as &str;
+                    base = node_to_html(child, base, innermost);
+                    base += &format!("{}</{}>", line, inner_tag) as &str;
+                }
+            }
+        }
+        
+        base += &format!("{}</{}>", indent(depth), outer_tag) as &str;
+        base
+    };
+    
+    match node.kind {
+        NodeKind::Root(ref children) => {
+            parental("html", &attrs(&None), children, base)
+        }
+        NodeKind::Contents(ref children) => {
+            parental("body", &attrs(&None), children, base)
+        }
+        NodeKind::Chapter(ref data, ref label) => {
+            with_data("h1", &attrs(label), data, base)
+        }
+        NodeKind::Section(ref data, ref label) => {
+            with_data("h2", &attrs(label), data, base)
+        }
+        NodeKind::Subsection(ref data, ref label) => {
+            with_data("h3", &attrs(label), data, base)
+        }
+        NodeKind::Text(ref data, ref label) => {
+            with_data("p", &attrs(label), data, base)
+        }
+        NodeKind::List(ref children) => {
+            wrapped("ul", &attrs(&None), "li", children, base)
+        }
+        NodeKind::OrderedList(ref children, ref numbering) => {
+            let attrs = attrs(&None) + &numbering_attrs(numbering);
+            wrapped("ol", &attrs, "li", children, base)
+        }
+        NodeKind::Item(ref children, ref label) => {
+            parental("li", &attrs(label), children, base)
+        }
+        NodeKind::Table(ref children, ref label, ref align) => {
+            // html requires the caption to come before any rows.
+            let is_caption = |child: &Node| {
+                matches!(child.kind, NodeKind::Caption(_))
+            };
+            
+            let captions = children.iter().filter(|child| is_caption(child));
+            let rows = children.iter().filter(|child| !is_caption(child));
+            let grid = code_gen::table_grid(children, align);
+            
+            let mut base = base + &indent(depth);
+            base += &format!("<table{}>", attrs(label)) as &str;
+            for child in captions {
+                base = node_to_html(child, base, inner);
+            }
+
+            for (row, grid_row) in rows.zip(&grid.rows) {
+                base = row_to_html(row, grid_row, &grid, base, inner);
+            }
+
+            base + &indent(depth) + "</table>"
+        }
+        NodeKind::Row(ref children) => {
+            wrapped("tr", &attrs(&None), "td", children, base)
+        }
+        NodeKind::HeaderRow(ref children) => {
+            wrapped("tr", &attrs(&None), "th", children, base)
+        }
+        NodeKind::Cell(ref children, colspan, rowspan, header) => {
+            let attrs = attrs(&None);
+            let tag = if header { "th" } else { "td" };
+            cell_to_html(tag, &attrs, children, colspan, rowspan, base, depth)
+        }
+        NodeKind::Caption(ref data) => {
+            with_data("caption", &attrs(&None), data, base)
+        }
+    }
+}
+
+pub struct Html {
+    // whether to indent block elements by their depth, for readable diffs.
+    pub pretty: bool,
+}
+
+impl Backend for Html {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        match self.pretty {
+            true => {
+                let out = node_to_html(node, String::new(), Some(0));
+                base + out.trim_start() + "\n"
+            }
+            false => node_to_html(node, base, None),
+        }
+    }
+}
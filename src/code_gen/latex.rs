@@ -0,0 +1,285 @@
+use crate::parse::{Align, Node, NodeKind, Numbering, NumberStyle};
+use crate::code_gen::{self, Backend, Inline};
+
+// ipa and other non-ascii text is passed through as unicode, which needs a
+// unicode aware engine (xelatex or lualatex) and a font covering it.
+const PREAMBLE: &str = "\
+\\documentclass{article}
+\\usepackage{fontspec}
+\\usepackage[normalem]{ulem}
+\\usepackage{multirow}
+\\usepackage{hyperref}
+";
+
+fn escape(text: &str) -> String {
+    code_gen::escape(text, |ch| match ch {
+        '\\' => Some("\\textbackslash{}"),
+        '{' => Some("\\{"),
+        '}' => Some("\\}"),
+        '$' => Some("\\$"),
+        '&' => Some("\\&"),
+        '%' => Some("\\%"),
+        '#' => Some("\\#"),
+        '_' => Some("\\_"),
+        '~' => Some("\\textasciitilde{}"),
+        '^' => Some("\\textasciicircum{}"),
+        '\u{a0}' => Some("~"),
+        _ => None,
+    })
+}
+
+fn inline_to_latex(text: &str) -> String {
+    let mut out = String::new();
+    let mut closers = Vec::new();
+    for piece in code_gen::inline(text) {
+        match piece {
+            Inline::Text(text) => out += &escape(&text),
+            Inline::Open(tag, href) => {
+                let (open, close) = match (tag.as_str(), href) {
+                    ("b" | "strong", _) => ("\\textbf{".to_string(), "}"),
+                    ("i" | "em", _) => ("\\textit{".to_string(), "}"),
+                    ("sub", _) => ("\\textsubscript{".to_string(), "}"),
+                    ("sup", _) => ("\\textsuperscript{".to_string(), "}"),
+                    ("s" | "del", _) => ("\\sout{".to_string(), "}"),
+
+                    // references to labels are links starting with `#`.
+                    ("a", Some(href)) => match href.strip_prefix('#') {
+                        Some(label) => {
+                            (format!("\\hyperref[{}]{{", label), "}")
+                        }
+                        None => (format!("\\href{{{}}}{{", href), "}"),
+                    }
+
+                    // other html tags have no meaning in latex.
+                    _ => (String::new(), ""),
+                };
+
+                out += &open;
+                closers.push(close);
+            }
+            Inline::Close(_) => out += closers.pop().unwrap_or(""),
+        }
+    }
+
+    out.trim().to_string()
+}
+
+fn label(label: &Option<String>) -> String {
+    match label {
+        Some(label) => format!("\\label{{{}}}", label),
+        None => String::new(),
+    }
+}
+
+fn blocks(children: &[Box<Node>]) -> String {
+    children
+        .iter()
+        .map(|child| node_to_latex(child))
+        .filter(|block| !block.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn list(
+    env: &str,
+    children: &[Box<Node>],
+    numbering: Option<&Numbering>,
+) -> String {
+    // non-decimal, offset or reversed numbering is written out explicitly.
+    let explicit = numbering.filter(|numbering| {
+        numbering.style != NumberStyle::Decimal
+            || numbering.start.is_some()
+            || numbering.reversed
+    });
+
+    let mut out = format!("\\begin{{{}}}\n", env);
+    for (i, child) in children.iter().enumerate() {
+        out += &match explicit {
+            Some(numbering) => {
+                let num = numbering.number(i, children.len());
+                format!("\\item[{}.] ", numbering.style.format(num))
+            }
+            None => String::from("\\item "),
+        };
+
+        out += &match child.kind {
+            NodeKind::Text(ref data, ref lbl) => {
+                label(lbl) + &inline_to_latex(data)
+            }
+            NodeKind::Item(ref children, ref lbl) => {
+                label(lbl) + &blocks(children)
+            }
+            _ => node_to_latex(child),
+        };
+
+        out += "\n";
+    }
+
+    out + &format!("\\end{{{}}}", env)
+}
+
+fn table_cell(nodes: &[&Node]) -> String {
+    match nodes {
+        [] => String::new(),
+        [text] if matches!(text.kind, NodeKind::Text(..)) => {
+            node_to_latex(text)
+        }
+
+        // only a box can hold paragraphs and lists inside of a table cell.
+        _ => format!(
+            "\\parbox[t]{{0.3\\textwidth}}{{{}}}",
+            nodes
+                .iter()
+                .map(|node| node_to_latex(node))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        ),
+    }
+}
+
+// numeric columns are right aligned, as decimal alignment needs a package.
+fn column_type(align: Align) -> &'static str {
+    match align {
+        Align::Left => "l",
+        Align::Right | Align::Numeric => "r",
+        Align::Center => "c",
+    }
+}
+
+fn table(
+    children: &[Box<Node>],
+    lbl: &Option<String>,
+    align: &[Align],
+) -> String {
+    let grid = code_gen::table_grid(children, align);
+    let columns = (0..grid.width.max(1))
+        .map(|col| column_type(grid.align(col)))
+        .collect::<String>();
+
+    let mut out = format!("\\begin{{tabular}}{{{}}}\n", columns);
+    
+    for row in &grid.rows {
+        // latex needs empty cells in place of those covered by row spans.
+        let mut cells = Vec::new();
+        let mut col = 0;
+        for cell in &row.cells {
+            while col < cell.col {
+                cells.push(String::new());
+                col += 1;
+            }
+
+            let mut text = table_cell(&cell.nodes);
+            if cell.is_header {
+                text = format!("\\textbf{{{}}}", text);
+            }
+
+            if cell.rowspan > 1 {
+                text = format!(
+                    "\\multirow{{{}}}{{*}}{{{}}}",
+                    cell.rowspan,
+                    text,
+                );
+            }
+
+            if cell.colspan > 1 {
+                text = format!(
+                    "\\multicolumn{{{}}}{{{}}}{{{}}}",
+                    cell.colspan,
+                    column_type(grid.align(cell.col)),
+                    text,
+                );
+            }
+
+            cells.push(text);
+            col += cell.colspan;
+        }
+
+        out += &format!("{} \\\\\n", cells.join(" & "));
+        if row.is_header {
+            out += "\\hline\n";
+        }
+    }
+
+    out += "\\end{tabular}";
+
+    // only a floating table can have a caption. without one, a label
+    // needs its own anchor, or references would jump to the section.
+    let caption = match (grid.caption, lbl) {
+        (None, None) => return out,
+        (Some(caption), _) => {
+            format!("\\caption{{{}}}", inline_to_latex(caption))
+        }
+        (None, Some(_)) => "\\phantomsection".to_string(),
+    };
+
+    format!(
+        "\\begin{{table}}[h]\n\\centering\n{}{}\n{}\n\\end{{table}}",
+        caption,
+        label(lbl),
+        out,
+    )
+}
+
+fn node_to_latex(node: &Node) -> String {
+    let heading = |cmd, data, lbl| {
+        format!("\\{}{{{}}}{}", cmd, inline_to_latex(data), label(lbl))
+    };
+
+    match node.kind {
+        NodeKind::Root(ref children) => format!(
+            "{}\n\\begin{{document}}\n\n{}\n\n\\end{{document}}\n",
+            PREAMBLE,
+            blocks(children),
+        ),
+        NodeKind::Contents(ref children) => blocks(children),
+        NodeKind::Chapter(ref data, ref lbl) => heading("section", data, lbl),
+        NodeKind::Section(ref data, ref lbl) => {
+            heading("subsection", data, lbl)
+        }
+        NodeKind::Subsection(ref data, ref lbl) => {
+            heading("subsubsection", data, lbl)
+        }
+        NodeKind::Text(ref data, ref lbl) => {
+            label(lbl) + &inline_to_latex(data)
+        }
+        NodeKind::List(ref children) => list("itemize", children, None),
+        NodeKind::OrderedList(ref children, ref numbering) => {
+            list("enumerate", children, Some(numbering))
+        }
+        NodeKind::Item(ref children, ref lbl) => label(lbl) + &blocks(children),
+        NodeKind::Table(ref children, ref lbl, ref align) => {
+            table(children, lbl, align)
+        }
+        NodeKind::Row(ref children) | NodeKind::HeaderRow(ref children) => {
+            children
+                .iter()
+                .map(|child| node_to_latex(child))
+                .collect::<Vec<_>>()
+                .join(" & ")
+        }
+        NodeKind::Cell(ref children, ..) => {
+            let nodes = children.iter().map(|child| &**child);
+            table_cell(&nodes.collect::<Vec<_>>())
+        }
+        NodeKind::Caption(ref data) => inline_to_latex(data),
+    }
+}
+
+pub struct Latex;
+
+impl Backend for Latex {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        base + &node_to_latex(node)
+    }
+
+    fn deprotect(&self, prot_code: &str) -> String {
+        match prot_code {
+            "LB" => "\\{",
+            "RB" => "\\}",
+            "EC" => "]",
+            "P_" => ".",
+            "A_" => "@",
+            _ => "",
+        }.to_string()
+    }
+}
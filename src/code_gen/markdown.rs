@@ -0,0 +1,290 @@
+use crate::parse::{Align, Node, NodeKind, NumberStyle, Numbering};
+use crate::code_gen::{self, html, Backend, Inline};
+
+fn escape(text: &str) -> String {
+    code_gen::escape(text, |ch| match ch {
+        '\\' => Some("\\\\"),
+        '`' => Some("\\`"),
+        '*' => Some("\\*"),
+        '_' => Some("\\_"),
+        '[' => Some("\\["),
+        ']' => Some("\\]"),
+        '<' => Some("\\<"),
+        '>' => Some("\\>"),
+        _ => None,
+    })
+}
+
+fn inline_to_md(text: &str) -> String {
+    let mut out = String::new();
+    let mut hrefs = Vec::new();
+    for piece in code_gen::inline(text) {
+        match piece {
+            Inline::Text(text) => out += &escape(&text),
+            Inline::Open(tag, href) => match tag.as_str() {
+                "b" | "strong" => out += "**",
+                "i" | "em" => out += "*",
+                "s" | "del" => out += "~~",
+                "a" => {
+                    out += "[";
+                    hrefs.push(href.unwrap_or_default());
+                }
+
+                // markdown has no equivalent for e.g. `sub` and `sup`.
+                _ => out += &format!("<{}>", tag),
+            }
+            Inline::Close(tag) => match tag.as_str() {
+                "b" | "strong" => out += "**",
+                "i" | "em" => out += "*",
+                "s" | "del" => out += "~~",
+                "a" => {
+                    out += &format!("]({})", hrefs.pop().unwrap_or_default());
+                }
+                _ => out += &format!("</{}>", tag),
+            }
+        }
+    }
+
+    out.trim().to_string()
+}
+
+fn paragraph(text: &str) -> String {
+    lazy_regex! {
+        LIST_NUMBER = r"^([0-9]+)([.)])";
+    }
+
+    let text = inline_to_md(text);
+
+    // stop a paragraph from being read as a heading, a list or a quote.
+    // `*` in text is escaped already, but emphasis could start a bullet.
+    if text.starts_with(['#', '-', '+', '>']) || text.starts_with("* ") {
+        format!("\\{}", text)
+    } else {
+        LIST_NUMBER.replace(&text, "$1\\$2").to_string()
+    }
+}
+
+// markdown has no labels of its own, so references link to an empty anchor
+// written in front of the labelled node.
+fn anchor(label: &Option<String>) -> String {
+    label
+        .as_ref()
+        .map_or(String::new(), |label| format!("<a id=\"{}\"></a>", label))
+}
+
+// markdown list markers are decimal numbers of at most nine digits that count
+// up from the first one, so other lists are written in html instead.
+fn has_markers(numbering: &Numbering, item_cnt: usize) -> bool {
+    let last = numbering.number(item_cnt.saturating_sub(1), item_cnt);
+    let in_range = |num| (0..=999_999_999).contains(&num);
+    !numbering.reversed
+        && numbering.style == NumberStyle::Decimal
+        && in_range(numbering.number(0, item_cnt))
+        && in_range(last)
+}
+
+fn blocks(children: &[Box<Node>]) -> String {
+    let is_list = |node: &Node| matches!(
+        node.kind,
+        NodeKind::List(_) | NodeKind::OrderedList(..),
+    );
+    
+    let mut blocks = Vec::new();
+    let mut prev_list = false;
+    for child in children {
+        let block = node_to_md(child);
+        if block.is_empty() {
+            continue;
+        }
+        
+        // two adjacent lists would otherwise be merged into one.
+        if prev_list && is_list(child) {
+            blocks.push(String::from("<!-- -->"));
+        }
+
+        prev_list = is_list(child);
+        blocks.push(block);
+    }
+
+    blocks.join("\n\n")
+}
+
+// items are indented by the width of their marker, so that everything in a
+// list item, including nested lists, stays inside of it.
+fn list<F>(children: &[Box<Node>], marker: F) -> String
+where
+    F: Fn(usize) -> String,
+{
+    let mut items = Vec::new();
+    for (i, child) in children.iter().enumerate() {
+        let marker = marker(i) + " ";
+        let indent = " ".repeat(marker.len());
+        let item = node_to_md(child);
+        let mut lines = item.lines();
+        let mut out = marker + lines.next().unwrap_or("");
+        for line in lines {
+            out += "\n";
+            if !line.is_empty() {
+                out += &indent;
+                out += line;
+            }
+        }
+
+        items.push(out);
+    }
+
+    items.join("\n")
+}
+
+// pipe table cells cannot span several lines.
+fn pipe_cell(text: &str) -> String {
+    text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+fn table(children: &[Box<Node>], align: &[Align]) -> String {
+    let grid = code_gen::table_grid(children, align);
+    let mut header = None;
+    let mut rows = Vec::new();
+    for (i, row) in grid.rows.iter().enumerate() {
+        // pipe tables cannot merge cells, so the columns that a cell spans or
+        // that are covered by a row span are left empty instead.
+        let in_header = row.is_header && i == 0;
+        let mut cells = vec![String::new(); grid.width];
+        for cell in &row.cells {
+            let text = cell
+                .nodes
+                .iter()
+                .map(|node| node_to_md(node))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            // header cells outside of the header row are set in bold.
+            let text = pipe_cell(&text);
+            cells[cell.col] = match cell.is_header && !in_header {
+                true if !text.is_empty() => format!("**{}**", text),
+                _ => text,
+            };
+        }
+
+        match in_header {
+            true => header = Some(cells),
+            false => rows.push(cells),
+        }
+    }
+
+    // pipe tables always need a header, so an empty one is used if missing.
+    let width = grid.width.max(1);
+    let header = header.unwrap_or_default();
+
+    let row = |cells: &Vec<String>| {
+        let cells = (0..width)
+            .map(|i| cells.get(i).map_or("", |cell| cell.as_str()))
+            .collect::<Vec<_>>();
+
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut lines = Vec::new();
+    lines.push(row(&header));
+    // numeric columns are right aligned.
+    let delimiters = (0..width).map(|i| {
+        String::from(match grid.align(i) {
+            Align::Left => "---",
+            Align::Right | Align::Numeric => "---:",
+            Align::Center => ":---:",
+        })
+    });
+
+    lines.push(row(&delimiters.collect()));
+    lines.extend(rows.iter().map(row));
+
+    match grid.caption {
+        Some(caption) => {
+            let caption = inline_to_md(caption);
+            format!("*{}*\n\n{}", caption, lines.join("\n"))
+        }
+        None => lines.join("\n"),
+    }
+}
+
+fn node_to_md(node: &Node) -> String {
+    match node.kind {
+        NodeKind::Root(ref children) => blocks(children) + "\n",
+        NodeKind::Contents(ref children) => blocks(children),
+        NodeKind::Chapter(ref data, ref label) => {
+            format!("# {}{}", anchor(label), inline_to_md(data))
+        }
+        NodeKind::Section(ref data, ref label) => {
+            format!("## {}{}", anchor(label), inline_to_md(data))
+        }
+        NodeKind::Subsection(ref data, ref label) => {
+            format!("### {}{}", anchor(label), inline_to_md(data))
+        }
+        NodeKind::Text(ref data, ref label) => anchor(label) + &paragraph(data),
+        NodeKind::List(ref children) => list(children, |_| "-".to_string()),
+        NodeKind::OrderedList(ref children, ref numbering)
+            if !has_markers(numbering, children.len()) =>
+        {
+            // the blank lines around the items let them hold markdown.
+            let items = children
+                .iter()
+                .map(|child| format!("<li>\n\n{}\n\n</li>", node_to_md(child)))
+                .collect::<Vec<_>>();
+
+            format!(
+                "<ol{}>\n{}\n</ol>",
+                html::numbering_attrs(numbering),
+                items.join("\n"),
+            )
+        }
+        NodeKind::OrderedList(ref children, ref numbering) => {
+            list(children, |i| {
+                format!("{}.", numbering.number(i, children.len()))
+            })
+        }
+        // an anchor can only go in front of text, and has a paragraph of its
+        // own otherwise.
+        NodeKind::Item(ref children, None) => blocks(children),
+        NodeKind::Item(ref children, ref label) => match children.first() {
+            Some(child) if matches!(child.kind, NodeKind::Text(..)) => {
+                anchor(label) + &blocks(children)
+            }
+            _ => format!("{}\n\n{}", anchor(label), blocks(children)),
+        }
+        NodeKind::Table(ref children, None, ref align) => {
+            table(children, align)
+        }
+        NodeKind::Table(ref children, ref label, ref align) => {
+            format!("{}\n\n{}", anchor(label), table(children, align))
+        }
+        NodeKind::Row(ref children)
+        | NodeKind::HeaderRow(ref children)
+        | NodeKind::Cell(ref children, ..) => blocks(children),
+        NodeKind::Caption(ref data) => paragraph(data),
+    }
+}
+
+pub struct Markdown;
+
+impl Backend for Markdown {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        base + &node_to_md(node)
+    }
+
+    fn deprotect(&self, prot_code: &str) -> String {
+        match prot_code {
+            "EC" => "\\]",
+            "LB" => "{",
+            "RB" => "}",
+            "P_" => ".",
+            "A_" => "@",
+            _ => "",
+        }.to_string()
+    }
+}
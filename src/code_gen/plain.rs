@@ -0,0 +1,372 @@
+use unicode_width::UnicodeWidthStr;
+
+use crate::parse::{Align, Node, NodeKind};
+use crate::code_gen::{self, Backend, Inline, Grid, GridCell};
+
+// columns narrower than this are not shrunk further to fit a table on a line.
+const MIN_COLUMN_WIDTH: usize = 8;
+
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ').filter(|word| !word.is_empty()) {
+        let line_width = line.width() + 1 + word.width();
+        if !line.is_empty() && line_width > width {
+            lines.push(line);
+            line = String::new();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+
+        line += word;
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+fn align(text: &str, width: usize, align: Align) -> String {
+    let space = width.saturating_sub(text.width());
+    match align {
+        Align::Left => pad(text, width),
+        Align::Right | Align::Numeric => {
+            format!("{}{}", " ".repeat(space), text)
+        }
+        Align::Center => format!(
+            "{}{}{}",
+            " ".repeat(space / 2),
+            text,
+            " ".repeat(space - space / 2),
+        ),
+    }
+}
+
+// the width of the decimal point and the digits after it, if there are any.
+fn fraction_width(text: &str) -> usize {
+    text.trim_end().find('.').map_or(0, |pos| text.trim_end()[pos..].width())
+}
+
+// prefixes the first line with `first` and all other non-empty lines with
+// spaces of the same width, giving a hanging indent.
+fn hang(lines: Vec<String>, first: &str) -> Vec<String> {
+    let indent = " ".repeat(first.width());
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) => format!("{}{}", first, line),
+            (_, true) => line,
+            (_, false) => format!("{}{}", indent, line),
+        })
+        .collect()
+}
+
+pub struct Plain {
+    pub width: usize,
+}
+
+impl Plain {
+    fn inline_to_plain(&self, text: &str) -> String {
+        let mut out = String::new();
+        let mut closers = Vec::new();
+        for piece in code_gen::inline(text) {
+            match piece {
+                Inline::Text(text) => out += &text,
+                Inline::Open(tag, href) => {
+                    let (open, close) = match (tag.as_str(), href) {
+                        ("b" | "strong", _) => ("*", String::from("*")),
+                        ("sub", _) => ("_", String::new()),
+                        ("sup", _) => ("^", String::new()),
+
+                        // references to labels are only written as text.
+                        ("a", Some(href)) if !href.starts_with('#') => {
+                            ("", format!(" <{}>", href))
+                        }
+                        _ => ("", String::new()),
+                    };
+
+                    out += open;
+                    closers.push(close);
+                }
+                Inline::Close(_) => out += &closers.pop().unwrap_or_default(),
+            }
+        }
+
+        // protected sequences must be gone before text is measured.
+        code_gen::postprocess(self, out.trim())
+    }
+
+    fn blocks<'a, I>(&self, nodes: I, width: usize) -> Vec<String>
+    where
+        I: IntoIterator<Item = &'a Node>,
+    {
+        let mut lines = Vec::new();
+        for node in nodes {
+            let block = self.node_lines(node, width);
+            if block.is_empty() {
+                continue;
+            }
+
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+
+            lines.extend(block);
+        }
+
+        lines
+    }
+
+    fn heading(&self, data: &str, underline: &str, width: usize)
+        -> Vec<String>
+    {
+        let mut lines = wrap(&self.inline_to_plain(data), width);
+        let line_width = lines.iter().map(|line| line.width()).max();
+        lines.push(underline.repeat(line_width.unwrap_or(0)));
+        lines
+    }
+
+    fn list<F>(&self, children: &[Box<Node>], width: usize, marker: F)
+        -> Vec<String>
+    where
+        F: Fn(usize) -> String,
+    {
+        // numbers are right aligned so that all items start in one column.
+        let markers = (0..children.len()).map(marker).collect::<Vec<_>>();
+        let marker_width = markers.iter().map(|m| m.width()).max();
+        let marker_width = marker_width.unwrap_or(0) + 1;
+
+        let mut lines = Vec::new();
+        for (child, marker) in children.iter().zip(markers) {
+            let marker = format!("{:>1$} ", marker, marker_width - 1);
+            let width = width.saturating_sub(marker_width).max(1);
+            lines.extend(hang(self.node_lines(child, width), &marker));
+        }
+
+        lines
+    }
+
+    fn cell_lines(&self, cell: &GridCell, width: usize) -> Vec<String> {
+        self.blocks(cell.nodes.iter().copied(), width)
+    }
+
+    fn table(
+        &self,
+        children: &[Box<Node>],
+        align: &[Align],
+        width: usize,
+    ) -> Vec<String> {
+        let grid = code_gen::table_grid(children, align);
+        let mut lines = match grid.caption {
+            Some(caption) => wrap(&self.inline_to_plain(caption), width),
+            None => Vec::new(),
+        };
+
+        lines.extend(self.grid_lines(&grid, width));
+        lines
+    }
+
+    // the rows of a table drawn with ascii borders, without its caption.
+    pub fn grid_lines(&self, grid: &Grid, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        if grid.width == 0 {
+            return lines;
+        }
+
+        // cells of numeric columns are padded after their numbers, so that
+        // the decimal points of a column line up once they are right aligned.
+        let is_numeric = |cell: &GridCell| {
+            cell.colspan == 1 && !cell.is_header
+                && grid.align(cell.col) == Align::Numeric
+        };
+
+        let mut fractions = vec![0; grid.width];
+        for cell in grid.rows.iter().flat_map(|row| row.cells.iter()) {
+            if is_numeric(cell) {
+                let lines = self.cell_lines(cell, usize::MAX);
+                for line in lines {
+                    let fraction = fraction_width(&line);
+                    fractions[cell.col] = fractions[cell.col].max(fraction);
+                }
+            }
+        }
+
+        let cell_lines = |cell: &GridCell, width: usize| {
+            let lines = self.cell_lines(cell, width);
+            if !is_numeric(cell) {
+                return lines;
+            }
+
+            lines
+                .into_iter()
+                .map(|line| {
+                    let missing = fractions[cell.col]
+                        .saturating_sub(fraction_width(&line));
+                    line.trim_end().to_string() + &" ".repeat(missing)
+                })
+                .collect()
+        };
+
+        let natural_width = |cell: &GridCell| {
+            let lines = cell_lines(cell, usize::MAX);
+            lines.iter().map(|line| line.width()).max().unwrap_or(0)
+        };
+
+        // a cell spanning several columns is as wide as those columns and the
+        // separators between them.
+        let span_width = |widths: &Vec<usize>, cell: &GridCell| {
+            let cols = &widths[cell.col..(cell.col + cell.colspan)];
+            cols.iter().sum::<usize>() + 3 * (cell.colspan - 1)
+        };
+
+        let mut widths = vec![1; grid.width];
+        for cell in grid.rows.iter().flat_map(|row| row.cells.iter()) {
+            if cell.colspan == 1 {
+                widths[cell.col] = widths[cell.col].max(natural_width(cell));
+            }
+        }
+
+        for cell in grid.rows.iter().flat_map(|row| row.cells.iter()) {
+            let missing = natural_width(cell)
+                .saturating_sub(span_width(&widths, cell));
+
+            widths[cell.col + cell.colspan - 1] += missing;
+        }
+
+        // the widest columns are shrunk until the table fits on a line.
+        let table_width = |widths: &Vec<usize>| {
+            widths.iter().sum::<usize>() + 3 * widths.len() + 1
+        };
+
+        // words are not broken, so a column is never shrunk below its longest
+        // word, which would push the borders of its rows out of line.
+        let min_width = |cell: &GridCell| {
+            let lines = cell_lines(cell, 1);
+            lines.iter().map(|line| line.width()).max().unwrap_or(0)
+        };
+
+        let mut min_widths = vec![MIN_COLUMN_WIDTH; grid.width];
+        for cell in grid.rows.iter().flat_map(|row| row.cells.iter()) {
+            if cell.colspan == 1 {
+                let min_width = min_width(cell);
+                min_widths[cell.col] = min_widths[cell.col].max(min_width);
+            }
+        }
+
+        while table_width(&widths) > width {
+            let widest = (0..grid.width)
+                .filter(|col| widths[*col] > min_widths[*col])
+                .max_by_key(|col| widths[*col]);
+
+            match widest {
+                Some(col) => widths[col] -= 1,
+                None => break,
+            }
+        }
+
+        for cell in grid.rows.iter().flat_map(|row| row.cells.iter()) {
+            let missing = min_width(cell)
+                .saturating_sub(span_width(&widths, cell));
+
+            widths[cell.col + cell.colspan - 1] += missing;
+        }
+
+        let border = |fill: &str| {
+            let cols = widths.iter().map(|width| fill.repeat(width + 2));
+            format!("+{}+", cols.collect::<Vec<_>>().join("+"))
+        };
+
+        lines.push(border("-"));
+        for row in &grid.rows {
+            let cells = row
+                .cells
+                .iter()
+                .map(|cell| {
+                    let width = span_width(&widths, cell);
+                    (cell, width, cell_lines(cell, width))
+                })
+                .collect::<Vec<_>>();
+
+            let height = cells.iter().map(|(_, _, lines)| lines.len()).max();
+            for i in 0..height.unwrap_or(0).max(1) {
+                let mut line = String::from("|");
+                let mut col = 0;
+                let mut cells = cells.iter().peekable();
+                while col < grid.width {
+                    match cells.next_if(|(cell, _, _)| cell.col == col) {
+                        Some((cell, width, cell_lines)) => {
+                            let text = cell_lines.get(i).map_or("", |l| l);
+                            let text = align(text, *width, grid.align(col));
+                            line += &format!(" {} |", text);
+                            col += cell.colspan;
+                        }
+                        None => {
+                            line += &format!(" {} |", " ".repeat(widths[col]));
+                            col += 1;
+                        }
+                    }
+                }
+
+                lines.push(line);
+            }
+
+            lines.push(border(if row.is_header { "=" } else { "-" }));
+        }
+
+        lines
+    }
+
+    fn node_lines(&self, node: &Node, width: usize) -> Vec<String> {
+        match node.kind {
+            NodeKind::Root(ref children) | NodeKind::Contents(ref children) => {
+                self.blocks(children.iter().map(|child| &**child), width)
+            }
+            NodeKind::Chapter(ref data, _) => self.heading(data, "=", width),
+            NodeKind::Section(ref data, _) => self.heading(data, "-", width),
+            NodeKind::Subsection(ref data, _) => self.heading(data, "~", width),
+            NodeKind::Text(ref data, _) | NodeKind::Caption(ref data) => {
+                wrap(&self.inline_to_plain(data), width)
+            }
+            NodeKind::List(ref children) => {
+                self.list(children, width, |_| String::from("*"))
+            }
+            NodeKind::OrderedList(ref children, ref numbering) => {
+                self.list(children, width, |i| {
+                    let num = numbering.number(i, children.len());
+                    format!("{}.", numbering.style.format(num))
+                })
+            }
+            NodeKind::Item(ref children, _)
+            | NodeKind::Cell(ref children, ..) => {
+                self.blocks(children.iter().map(|child| &**child), width)
+            }
+            NodeKind::Table(ref children, _, ref align) => {
+                self.table(children, align, width)
+            }
+            NodeKind::Row(ref children) | NodeKind::HeaderRow(ref children) => {
+                let cells = children
+                    .iter()
+                    .map(|child| self.node_lines(child, width).join(" "));
+
+                vec![cells.collect::<Vec<_>>().join(" | ")]
+            }
+        }
+    }
+}
+
+impl Backend for Plain {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        let lines = self.node_lines(node, self.width);
+        let lines = lines.iter().map(|line| line.trim_end());
+        base + &lines.collect::<Vec<_>>().join("\n") + "\n"
+    }
+}
@@ -0,0 +1,310 @@
+use crate::parse::{Align, Node, NodeKind};
+use crate::code_gen::{self, Backend, Inline};
+
+fn escape(text: &str) -> String {
+    code_gen::escape(text, |ch| match ch {
+        '\\' => Some("\\e"),
+        '\t' => Some(" "),
+        _ => None,
+    })
+}
+
+// a line starting with `.` or `'` would be read as a request.
+fn text_line(text: &str) -> String {
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn inline_to_roff(text: &str) -> String {
+    let font = |bold, italic| match (bold, italic) {
+        (true, true) => "\\f(BI",
+        (true, false) => "\\fB",
+        (false, true) => "\\fI",
+        (false, false) => "\\fR",
+    };
+
+    let mut out = String::new();
+    let mut bold = 0;
+    let mut italic = 0;
+    let mut closers = Vec::new();
+    for piece in code_gen::inline(text) {
+        match piece {
+            Inline::Text(text) => out += &escape(&text),
+            Inline::Open(tag, href) => match (tag.as_str(), href) {
+                ("b" | "strong", _) => {
+                    bold += 1;
+                    out += font(bold > 0, italic > 0);
+                    closers.push(String::from("b"));
+                }
+                ("i" | "em", _) => {
+                    italic += 1;
+                    out += font(bold > 0, italic > 0);
+                    closers.push(String::from("i"));
+                }
+
+                // references to labels are only written as text.
+                ("a", Some(href)) if !href.starts_with('#') => {
+                    closers.push(format!(" <{}>", escape(&href)));
+                }
+                _ => closers.push(String::new()),
+            }
+            Inline::Close(_) => match closers.pop().as_deref() {
+                Some("b") => {
+                    bold -= 1;
+                    out += font(bold > 0, italic > 0);
+                }
+                Some("i") => {
+                    italic -= 1;
+                    out += font(bold > 0, italic > 0);
+                }
+                Some(closer) => out += closer,
+                None => {}
+            }
+        }
+    }
+
+    text_line(out.trim())
+}
+
+fn blocks(children: &[Box<Node>]) -> String {
+    children
+        .iter()
+        .map(|child| node_to_roff(child))
+        .filter(|block| !block.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// a block inside of a list item or table cell, where paragraph and section
+// requests would end the item.
+fn inner_block(node: &Node) -> String {
+    match node.kind {
+        NodeKind::Chapter(ref data, _)
+        | NodeKind::Section(ref data, _)
+        | NodeKind::Subsection(ref data, _) => {
+            format!("\\fB{}\\fR", inline_to_roff(data))
+        }
+        NodeKind::Text(ref data, _) => inline_to_roff(data),
+        NodeKind::Item(ref children, _)
+        | NodeKind::Cell(ref children, ..) => {
+            children
+                .iter()
+                .map(|child| inner_block(child))
+                .collect::<Vec<_>>()
+                .join("\n.IP\n")
+        }
+        _ => format!(".RS\n{}\n.RE", node_to_roff(node)),
+    }
+}
+
+fn list<F>(children: &[Box<Node>], tag: F) -> String
+where
+    F: Fn(usize) -> String,
+{
+    // a bullet is one character wide, even though it is written as `\(bu`.
+    let tags = (0..children.len()).map(tag).collect::<Vec<_>>();
+    let indent = tags
+        .iter()
+        .map(|tag| tag.replace("\\(bu", "*").chars().count())
+        .max();
+
+    let indent = indent.unwrap_or(0) + 1;
+
+    let mut out = Vec::new();
+    for (child, tag) in children.iter().zip(tags) {
+        out.push(format!(".IP \"{}\" {}", tag, indent));
+        out.push(inner_block(child));
+    }
+
+    out.join("\n")
+}
+
+fn has_table(node: &Node) -> bool {
+    matches!(node.kind, NodeKind::Table(..))
+        || node.kind.children().iter().any(|child| has_table(child))
+}
+
+fn table(children: &[Box<Node>], align: &[Align]) -> String {
+    let grid = code_gen::table_grid(children, align);
+    // a paragraph ends the indentation of any list before the table.
+    let mut out = vec![String::from(".PP")];
+    if let Some(caption) = grid.caption {
+        out.push(format!("\\fI{}\\fR", inline_to_roff(caption)));
+    }
+
+    // tbl has no empty tables, so a table without rows is only its caption.
+    if grid.width == 0 {
+        return out.join("\n");
+    }
+
+    let mut formats = Vec::new();
+    let mut data = Vec::new();
+    let mut covered = vec![0; grid.width];
+    for row in &grid.rows {
+        let mut format = vec![String::from("l"); grid.width];
+        let mut cells = vec![String::new(); grid.width];
+        for col in 0..grid.width {
+            if covered[col] > 0 {
+                format[col] = String::from("^");
+                covered[col] -= 1;
+            }
+        }
+
+        for cell in &row.cells {
+            let text = match cell.nodes.as_slice() {
+                [node] if matches!(node.kind, NodeKind::Text(..)) => {
+                    inner_block(node)
+                }
+
+                // text blocks let a cell hold several lines.
+                nodes => format!(
+                    "T{{\n{}\nT}}",
+                    nodes
+                        .iter()
+                        .map(|node| inner_block(node))
+                        .collect::<Vec<_>>()
+                        .join("\n.br\n"),
+                ),
+            };
+
+            // tbl aligns numeric columns on their decimal points itself.
+            let key = match grid.align(cell.col) {
+                Align::Left => "l",
+                Align::Right => "r",
+                Align::Center => "c",
+                Align::Numeric if cell.colspan == 1 && !cell.is_header => "n",
+                Align::Numeric => "r",
+            };
+
+            format[cell.col] = match cell.is_header {
+                true => format!("{}B", key),
+                false => String::from(key),
+            };
+
+            let cols = cell.col..(cell.col + cell.colspan);
+            for col_format in &mut format[(cell.col + 1)..cols.end] {
+                *col_format = String::from("s");
+            }
+
+            for rows in &mut covered[cols] {
+                *rows = cell.rowspan - 1;
+            }
+
+            cells[cell.col] = text;
+        }
+
+        // spanned columns must not have data of their own.
+        let cells = cells
+            .into_iter()
+            .zip(format.iter())
+            .filter(|(_, format)| *format != "s")
+            .map(|(cell, _)| cell);
+
+        formats.push(format.join(" "));
+        data.push(cells.collect::<Vec<_>>().join("\t"));
+    }
+
+    out.push(String::from(".TS\nallbox;"));
+    out.push(formats.join("\n") + ".");
+    out.extend(data);
+    out.push(String::from(".TE"));
+    out.join("\n")
+}
+
+fn node_to_roff(node: &Node) -> String {
+    match node.kind {
+        NodeKind::Root(ref children) | NodeKind::Contents(ref children) => {
+            blocks(children)
+        }
+        NodeKind::Chapter(ref data, _) => {
+            format!(".SH {}", inline_to_roff(data))
+        }
+        NodeKind::Section(ref data, _) => {
+            format!(".SS {}", inline_to_roff(data))
+        }
+
+        // man pages have no third level of headings.
+        NodeKind::Subsection(ref data, _) => {
+            format!(".PP\n\\fB{}\\fR", inline_to_roff(data))
+        }
+        NodeKind::Text(ref data, _) => {
+            format!(".PP\n{}", inline_to_roff(data))
+        }
+        NodeKind::List(ref children) => {
+            list(children, |_| String::from("\\(bu"))
+        }
+        NodeKind::OrderedList(ref children, ref numbering) => {
+            list(children, |i| {
+                let num = numbering.number(i, children.len());
+                format!("{}.", numbering.style.format(num))
+            })
+        }
+        NodeKind::Item(..) | NodeKind::Cell(..) => inner_block(node),
+        NodeKind::Table(ref children, _, ref align) => table(children, align),
+        NodeKind::Row(ref children) | NodeKind::HeaderRow(ref children) => {
+            children
+                .iter()
+                .map(|child| inner_block(child))
+                .collect::<Vec<_>>()
+                .join("\t")
+        }
+        NodeKind::Caption(ref data) => {
+            format!(".PP\n\\fI{}\\fR", inline_to_roff(data))
+        }
+    }
+}
+
+pub struct Roff {
+    // the page title, optionally followed by the manual section, e.g.
+    // `vvsml.1`.
+    pub title: String,
+}
+
+impl Backend for Roff {
+    fn node_to_output(&self, node: &Node, base: String) -> String {
+        let (name, section) = match self.title.rsplit_once('.') {
+            Some((name, section))
+                if section.chars().all(|ch| ch.is_ascii_digit())
+                    && !section.is_empty() =>
+            {
+                (name, section)
+            }
+            _ => (self.title.as_str(), "1"),
+        };
+
+        // man pages tell `man` which preprocessors they need on their first
+        // line, `t` being tbl.
+        let preprocessors = match has_table(node) {
+            true => "'\\\" t\n",
+            false => "",
+        };
+
+        let header = match node.kind {
+            NodeKind::Root(_) => format!(
+                "{}.TH \"{}\" \"{}\"\n",
+                preprocessors,
+                escape(&name.to_uppercase()),
+                section,
+            ),
+            _ => String::new(),
+        };
+
+        base + &header + &node_to_roff(node) + "\n"
+    }
+
+    fn deprotect(&self, prot_code: &str) -> String {
+        match prot_code {
+            "LB" => "{",
+            "RB" => "}",
+            "EC" => "]",
+
+            // a protected `.` may end up at the start of a line.
+            "P_" => "\\&.",
+            "A_" => "@",
+            _ => "",
+        }.to_string()
+    }
+}
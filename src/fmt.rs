@@ -0,0 +1,236 @@
+use std::process;
+
+use crate::lang_util::CountLines;
+
+const INDENT: &str = "    ";
+
+// nodes whose main block holds other nodes, rather than text.
+const CONTAINERS: &[&str] = &[
+    "contents",
+    "list",
+    "ordered_list",
+    "item",
+    "table",
+    "row",
+    "header_row",
+    "cell",
+];
+
+const LEAVES: &[&str] = &[
+    "chapter",
+    "section",
+    "subsection",
+    "text",
+    "caption",
+];
+
+// walks over the unpreprocessed source between `pos` and `end`, so that
+// directives are kept as they were written.
+struct Scanner<'a> {
+    file_path: &'a str,
+    src: &'a str,
+    pos: usize,
+    end: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn fail(&self, msg: &str) -> ! {
+        let line = 1 + self.src.count_lines_in(0..self.pos);
+        error!(self.file_path, line, msg);
+        process::exit(-1);
+    }
+
+    fn peek(&self) -> Option<u8> {
+        match self.pos < self.end {
+            true => Some(self.src.as_bytes()[self.pos]),
+            false => None,
+        }
+    }
+
+    // skips whitespace, returning the number of line breaks skipped.
+    fn skip_whitespace(&mut self) -> usize {
+        let mut line_breaks = 0;
+        while let Some(ch) = self.peek().filter(u8::is_ascii_whitespace) {
+            if ch == b'\n' {
+                line_breaks += 1;
+            }
+
+            self.pos += 1;
+        }
+
+        line_breaks
+    }
+
+    // skips whitespace only if it is followed by `ch`.
+    fn next_is(&mut self, ch: u8) -> bool {
+        let pos = self.pos;
+        self.skip_whitespace();
+        if self.peek() == Some(ch) {
+            return true;
+        }
+
+        self.pos = pos;
+        false
+    }
+
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while let Some(ch) = self.peek() {
+            if !(ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'.') {
+                break;
+            }
+
+            self.pos += 1;
+        }
+
+        &self.src[start..self.pos]
+    }
+
+    // the range inside of a `{}` block, which may contain nested blocks and
+    // escaped characters.
+    fn block(&mut self) -> (usize, usize) {
+        let start = self.pos + 1;
+        let mut depth = 0;
+        while let Some(ch) = self.peek() {
+            if self.src[self.pos..self.end].starts_with("]]$") {
+                self.pos += 3;
+                let escaped = self.src[self.pos..self.end].chars().next();
+                self.pos += escaped.map_or(0, char::len_utf8);
+                continue;
+            }
+
+            self.pos += 1;
+            match ch {
+                b'{' => depth += 1,
+                b'}' if depth == 1 => return (start, self.pos - 1),
+                b'}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        self.pos = start - 1;
+        self.fail("unterminated block");
+    }
+
+    // the text inside of an `[]` attribute block, which may contain quotes.
+    fn attributes(&mut self) -> &'a str {
+        let start = self.pos + 1;
+        let mut quoted = false;
+        while let Some(ch) = self.peek() {
+            self.pos += 1;
+            match ch {
+                b'"' => quoted = !quoted,
+                b']' if !quoted => return self.src[start..self.pos - 1].trim(),
+                _ => {}
+            }
+        }
+
+        self.pos = start - 1;
+        self.fail("unterminated attribute block");
+    }
+
+    fn sub_scanner(&self, (start, end): (usize, usize)) -> Scanner<'a> {
+        Scanner {
+            file_path: self.file_path,
+            src: self.src,
+            pos: start,
+            end,
+        }
+    }
+
+    fn directive(&mut self, indent: &str) -> String {
+        let mut out = format!("{}{}", indent, self.word());
+        while self.next_is(b'{') {
+            let (start, end) = self.block();
+            out += &format!(" {{{}}}", &self.src[start..end]);
+        }
+
+        out
+    }
+
+    fn node(&mut self, depth: usize) -> Vec<String> {
+        let indent = INDENT.repeat(depth);
+        let keyword = self.word();
+        let is_container = CONTAINERS.contains(&keyword);
+        if !is_container && !LEAVES.contains(&keyword) {
+            let err_msg = match keyword {
+                "" => format!("unexpected character: {}", self.src[self.pos..]
+                    .chars()
+                    .next()
+                    .unwrap_or(' ')),
+                _ => format!("unknown node: {}", keyword),
+            };
+
+            self.fail(&err_msg);
+        }
+
+        let mut head = format!("{}{}", indent, keyword);
+        if self.next_is(b'[') {
+            head += &format!(" [{}]", self.attributes());
+        }
+
+        let mut blocks = Vec::new();
+        while self.next_is(b'{') {
+            blocks.push(self.block());
+        }
+
+        let main = blocks.pop().unwrap_or_else(|| {
+            self.fail(&format!("expected a block after {}", keyword));
+        });
+
+        // options are only separated by whitespace, so it is normalized.
+        for (start, end) in blocks {
+            let opts = self.src[start..end].split_whitespace();
+            head += &format!(" {{{}}}", opts.collect::<Vec<_>>().join(" "));
+        }
+
+        if !is_container {
+            return vec![format!("{} {{{}}}", head, &self.src[main.0..main.1])];
+        }
+
+        let children = self.sub_scanner(main).nodes(depth + 1);
+        if children.is_empty() {
+            return vec![format!("{} {{}}", head)];
+        }
+
+        let mut lines = vec![format!("{} {{", head)];
+        lines.extend(children);
+        lines.push(format!("{}}}", indent));
+        lines
+    }
+
+    // the formatted lines of all nodes and directives until the end, keeping
+    // single blank lines between them.
+    fn nodes(&mut self, depth: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line_breaks = self.skip_whitespace();
+            if self.peek().is_none() {
+                return lines;
+            }
+
+            if line_breaks > 1 && !lines.is_empty() {
+                lines.push(String::new());
+            }
+
+            if self.peek() == Some(b'.') {
+                lines.push(self.directive(&INDENT.repeat(depth)));
+            } else {
+                lines.extend(self.node(depth));
+            }
+        }
+    }
+}
+
+// reformats a source file with one node per line, indented by its nesting
+// depth. text, attributes and directive arguments are left as they are.
+pub fn format(file_path: &str, src: &str) -> String {
+    let mut scanner = Scanner {
+        file_path,
+        src,
+        pos: 0,
+        end: src.len(),
+    };
+
+    scanner.nodes(0).join("\n") + "\n"
+}
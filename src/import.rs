@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::process;
+
+use pulldown_cmark::{html, Options, Parser};
+
+const INDENT: &str = "    ";
+
+// directives that a `.` in imported text could otherwise be read as. the
+// lexers match these as prefixes, so `.formatting` has to be escaped too.
+const DIRECTIVES: &[&str] = &[
+    "define_macro",
+    "macro",
+    "format",
+    "link",
+    "unicode",
+    "replace_all",
+    "external_table",
+    "external_csv",
+    "external_tsv",
+    "external_json_table",
+    "vvtab",
+    "for_each_row",
+    "ref",
+];
+
+// elements that are not written back out, along with all of their contents.
+const SKIPPED: &[&str] = &["head", "script", "style", "template"];
+
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta",
+    "source", "track", "wbr",
+];
+
+const BLOCKS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "caption", "dd",
+    "details", "div", "dl", "dt", "figcaption", "figure", "footer", "form",
+    "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "html", "li", "main",
+    "nav", "ol", "p", "pre", "section", "summary", "table", "tbody", "td",
+    "tfoot", "th", "thead", "tr", "ul",
+];
+
+// elements that end a paragraph which has not been closed explicitly.
+const CLOSES_P: &[&str] = &[
+    "address", "article", "aside", "blockquote", "div", "dl", "figure",
+    "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "main",
+    "nav", "ol", "p", "pre", "section", "table", "ul",
+];
+
+#[derive(Debug)]
+enum Html {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attrs: HashMap<String, String>,
+    children: Vec<Html>,
+}
+
+impl Element {
+    fn new(name: &str, attrs: HashMap<String, String>) -> Element {
+        Element {
+            name: name.to_string(),
+            attrs,
+            children: Vec::new(),
+        }
+    }
+}
+
+fn parse_attrs(text: &str) -> HashMap<String, String> {
+    lazy_regex! {
+        ATTR = r#"(?x)
+            ([A-Za-z_:][-A-Za-z0-9_:.]*)
+            (?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+)))?
+        "#;
+    }
+
+    ATTR.captures_iter(text)
+        .map(|caps| {
+            let value = caps.get(2).or(caps.get(3)).or(caps.get(4));
+            (
+                caps[1].to_lowercase(),
+                value.map_or(String::new(), |value| value.as_str().to_string()),
+            )
+        })
+        .collect()
+}
+
+// closes the innermost open `name` element, if there is one below any of the
+// elements in `bounds`.
+fn close(stack: &mut Vec<Element>, name: &str, bounds: &[&str]) {
+    let open = stack
+        .iter()
+        .rposition(|el| el.name == name || bounds.contains(&el.name.as_str()));
+
+    if let Some(pos) = open.filter(|pos| stack[*pos].name == name) {
+        while stack.len() > pos {
+            let el = stack.pop().unwrap();
+            let parent = stack.last_mut().unwrap();
+            parent.children.push(Html::Element(el));
+        }
+    }
+}
+
+// builds an element tree from html, closing elements like browsers would for
+// common hand-written html, such as unclosed paragraphs and list items.
+fn parse_html(src: &str) -> Element {
+    lazy_regex! {
+        TAG = r#"(?x)
+            <!--[\s\S]*?-->
+            | <[!?][^>]*>
+            | <(/?)([A-Za-z][A-Za-z0-9]*)((?:[^>"']|"[^"]*"|'[^']*')*)>
+        "#;
+    }
+
+    let mut stack = vec![Element::new("", HashMap::new())];
+    let mut pos = 0;
+    while let Some(caps) = TAG.captures_at(src, pos) {
+        let mat = caps.get(0).unwrap();
+        let text = &src[pos..mat.start()];
+        if !text.is_empty() {
+            let parent = stack.last_mut().unwrap();
+            parent.children.push(Html::Text(text.to_string()));
+        }
+
+        pos = mat.end();
+        let name = match caps.get(2) {
+            Some(name) => name.as_str().to_lowercase(),
+            None => continue,
+        };
+
+        if &caps[1] == "/" {
+            close(&mut stack, &name, &[]);
+            continue;
+        }
+
+        if CLOSES_P.contains(&name.as_str()) {
+            close(&mut stack, "p", &["li", "td", "th", "blockquote", "div"]);
+        }
+
+        match name.as_str() {
+            "li" => close(&mut stack, "li", &["ul", "ol"]),
+            "td" | "th" => {
+                close(&mut stack, "td", &["tr", "table"]);
+                close(&mut stack, "th", &["tr", "table"]);
+            }
+            "tr" => close(&mut stack, "tr", &["table"]),
+            _ => {}
+        }
+
+        let el = Element::new(&name, parse_attrs(&caps[3]));
+        let self_closing = caps[3].trim_end().ends_with('/');
+        if SKIPPED.contains(&name.as_str()) {
+            // the contents of e.g. scripts are not html, so they are skipped
+            // up to the closing tag without looking for other tags.
+            let closing = format!("</{}", name);
+            pos = src[pos..]
+                .to_lowercase()
+                .find(&closing)
+                .map_or(src.len(), |end| pos + end);
+        } else if VOID.contains(&name.as_str()) || self_closing {
+            stack.last_mut().unwrap().children.push(Html::Element(el));
+        } else {
+            stack.push(el);
+        }
+    }
+
+    let parent = stack.last_mut().unwrap();
+    parent.children.push(Html::Text(src[pos..].to_string()));
+    while stack.len() > 1 {
+        let el = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(Html::Element(el));
+    }
+
+    stack.pop().unwrap()
+}
+
+// escapes text so that it is read back exactly as it was.
+fn escape(text: &str) -> String {
+    lazy_regex! {
+        SPECIAL = r"[{}]|\](?:\]\$)|\.[a-z_]+";
+    }
+
+    SPECIAL
+        .replace_all(text, |caps: &regex::Captures| {
+            let mat = &caps[0];
+            let is_directive = DIRECTIVES
+                .iter()
+                .any(|directive| mat[1..].starts_with(directive));
+
+            match mat.chars().next() {
+                Some('.') if !is_directive => mat.to_string(),
+                _ => format!("]]${}", mat),
+            }
+        })
+        .to_string()
+}
+
+fn inline<'a, I>(nodes: I) -> String
+where
+    I: IntoIterator<Item = &'a Html>,
+{
+    let mut out = String::new();
+    for node in nodes {
+        let el = match node {
+            Html::Text(text) => {
+                out += &escape(text);
+                continue;
+            }
+            Html::Element(el) => el,
+        };
+
+        let inner = inline(&el.children);
+        let spec = match el.name.as_str() {
+            "b" | "strong" => "b",
+            "i" | "em" | "cite" | "dfn" | "var" => "i",
+            "s" | "del" | "strike" => "s",
+            "sub" => "_",
+            "sup" => "^",
+            "br" => {
+                out += " ";
+                continue;
+            }
+            "a" => {
+                match el.attrs.get("href") {
+                    Some(href) => out += &format!(
+                        ".link {{{}}} {{{}}}",
+                        inner,
+                        href.replace('{', "]]${").replace('}', "]]$}"),
+                    ),
+                    None => out += &inner,
+                }
+
+                continue;
+            }
+            _ => "",
+        };
+
+        match (spec, inner.trim().is_empty()) {
+            ("", _) | (_, true) => out += &inner,
+            _ => out += &format!(".format {{{}}} {{{}}}", spec, inner.trim()),
+        }
+    }
+
+    out
+}
+
+// a node to be written out, with either text or child nodes in its main
+// block.
+struct Node {
+    keyword: &'static str,
+    opts: Vec<String>,
+    body: Body,
+}
+
+enum Body {
+    Text(String),
+    Children(Vec<Node>),
+}
+
+fn text_node(keyword: &'static str, text: String) -> Node {
+    lazy_regex! {
+        WHITESPACE = r"\s+";
+    }
+
+    let text = WHITESPACE.replace_all(text.trim(), " ");
+    Node {
+        keyword,
+        opts: Vec::new(),
+        body: Body::Text(text.to_string()),
+    }
+}
+
+fn is_block(node: &Html) -> bool {
+    match node {
+        Html::Element(el) => BLOCKS.contains(&el.name.as_str()),
+        Html::Text(_) => false,
+    }
+}
+
+// the only text node of `nodes`, if there is nothing else.
+fn single_text(mut nodes: Vec<Node>) -> Result<Node, Vec<Node>> {
+    match nodes.as_slice() {
+        [Node { keyword: "text", .. }] => Ok(nodes.pop().unwrap()),
+        _ => Err(nodes),
+    }
+}
+
+fn list_items(el: &Element) -> Vec<Node> {
+    let mut items = Vec::new();
+    for child in &el.children {
+        match child {
+            Html::Element(li) if li.name == "li" => {
+                items.push(match single_text(blocks(&li.children)) {
+                    Ok(text) => text,
+                    Err(children) => Node {
+                        keyword: "item",
+                        opts: Vec::new(),
+                        body: Body::Children(children),
+                    },
+                });
+            }
+            Html::Element(_) => items.extend(blocks([child])),
+            Html::Text(_) => {}
+        }
+    }
+
+    items
+}
+
+fn ordered_list_opts(el: &Element) -> Vec<String> {
+    let mut opts = Vec::new();
+    if let Some(start) = el.attrs.get("start") {
+        opts.push(format!("start={}", start.trim()));
+    }
+
+    let style = match el.attrs.get("type").map(String::as_str) {
+        Some("a") => Some("lower-alpha"),
+        Some("A") => Some("upper-alpha"),
+        Some("i") => Some("lower-roman"),
+        Some("I") => Some("upper-roman"),
+        _ => None,
+    };
+
+    if let Some(style) = style {
+        opts.push(format!("style={}", style));
+    }
+
+    if el.attrs.contains_key("reversed") {
+        opts.push(String::from("reversed"));
+    }
+
+    opts
+}
+
+// `in_header` is whether the cell is in a header row, where every cell is a
+// header cell.
+fn table_cell(el: &Element, in_header: bool) -> Node {
+    let mut opts = Vec::new();
+    for span in ["colspan", "rowspan"] {
+        if let Some(value) = el.attrs.get(span) {
+            if value.trim() != "1" {
+                opts.push(format!("{}={}", span, value.trim()));
+            }
+        }
+    }
+
+    if el.name == "th" && !in_header {
+        opts.push(String::from("header"));
+    }
+
+    // cells only need to be written out as such if they span several rows
+    // or columns, are header cells, or hold more than a single text.
+    let children = match (single_text(blocks(&el.children)), opts.is_empty()) {
+        (Ok(text), true) => return text,
+        (Err(children), true) if children.is_empty() => {
+            return text_node("text", String::new());
+        }
+        (Ok(text), false) => vec![text],
+        (Err(children), _) => children,
+    };
+
+    Node {
+        keyword: "cell",
+        opts,
+        body: Body::Children(children),
+    }
+}
+
+// the alignment of a cell, given either by the old `align` attribute or by a
+// `text-align` style, as markdown tables are converted.
+fn cell_align(el: &Element) -> &'static str {
+    lazy_regex! {
+        TEXT_ALIGN = r"text-align:\s*([a-z]+)";
+    }
+
+    let style = el.attrs.get("style").and_then(|style| {
+        TEXT_ALIGN.captures(style).map(|caps| caps[1].to_string())
+    });
+
+    match style.as_deref().or(el.attrs.get("align").map(|s| s.as_str())) {
+        Some("right") => "right",
+        Some("center") => "center",
+        _ => "left",
+    }
+}
+
+// the rows and caption of a table, which may be grouped into a head, body
+// and foot that vvsml does not have. the cells of the first row give the
+// alignment of their columns.
+fn table_rows(el: &Element, rows: &mut Vec<Node>, align: &mut Vec<&str>) {
+    for child in &el.children {
+        let child = match child {
+            Html::Element(child) => child,
+            Html::Text(_) => continue,
+        };
+
+        match child.name.as_str() {
+            "caption" => rows.insert(0, text_node(
+                "caption",
+                inline(&child.children),
+            )),
+            "thead" | "tbody" | "tfoot" => table_rows(child, rows, align),
+            "tr" => {
+                let cells = child.children.iter().filter_map(|cell| {
+                    match cell {
+                        Html::Element(cell)
+                            if cell.name == "td" || cell.name == "th" =>
+                        {
+                            Some(cell)
+                        }
+                        _ => None,
+                    }
+                });
+
+                let cells = cells.collect::<Vec<_>>();
+                let is_header = !cells.is_empty()
+                    && cells.iter().all(|cell| cell.name == "th");
+
+                let is_first = rows.iter().all(|row| row.keyword == "caption");
+                if is_first {
+                    align.extend(cells.iter().map(|cell| cell_align(cell)));
+                }
+
+                let cells = cells
+                    .into_iter()
+                    .map(|cell| table_cell(cell, is_header))
+                    .collect();
+
+                rows.push(Node {
+                    keyword: if is_header { "header_row" } else { "row" },
+                    opts: Vec::new(),
+                    body: Body::Children(cells),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn block(el: &Element, out: &mut Vec<Node>) {
+    let children = |keyword, opts, children| Node {
+        keyword,
+        opts,
+        body: Body::Children(children),
+    };
+
+    match el.name.as_str() {
+        "h1" => out.push(text_node("chapter", inline(&el.children))),
+        "h2" => out.push(text_node("section", inline(&el.children))),
+        "h3" | "h4" | "h5" | "h6" => {
+            out.push(text_node("subsection", inline(&el.children)));
+        }
+        "p" | "pre" | "dt" | "dd" | "figcaption" | "summary" | "address" => {
+            let text = inline(&el.children);
+            if !text.trim().is_empty() {
+                out.push(text_node("text", text));
+            }
+        }
+        "ul" => out.push(children("list", Vec::new(), list_items(el))),
+        "ol" => {
+            let opts = ordered_list_opts(el);
+            out.push(children("ordered_list", opts, list_items(el)));
+        }
+        "table" => {
+            let mut rows = Vec::new();
+            let mut align = Vec::new();
+            table_rows(el, &mut rows, &mut align);
+
+            // columns are left aligned unless given otherwise.
+            while align.last() == Some(&"left") {
+                align.pop();
+            }
+
+            let opts = match align.is_empty() {
+                true => Vec::new(),
+                false => vec![format!("align={}", align.join(","))],
+            };
+
+            out.push(children("table", opts, rows));
+        }
+        "hr" => {}
+
+        // other blocks, like `div`, only group their contents.
+        _ => out.extend(blocks(&el.children)),
+    }
+}
+
+// converts block elements into nodes, putting any inline content between
+// them into text nodes.
+fn blocks<'a, I>(nodes: I) -> Vec<Node>
+where
+    I: IntoIterator<Item = &'a Html>,
+{
+    let mut out = Vec::new();
+    let mut pending = Vec::new();
+    let flush = |pending: &mut Vec<&Html>, out: &mut Vec<Node>| {
+        let text = inline(pending.drain(..));
+        if !text.trim().is_empty() {
+            out.push(text_node("text", text));
+        }
+    };
+
+    for node in nodes {
+        match node {
+            Html::Element(el) if is_block(node) => {
+                flush(&mut pending, &mut out);
+                block(el, &mut out);
+            }
+            _ => pending.push(node),
+        }
+    }
+
+    flush(&mut pending, &mut out);
+    out
+}
+
+fn render(node: &Node, depth: usize, lines: &mut Vec<String>) {
+    let indent = INDENT.repeat(depth);
+    let mut head = format!("{}{}", indent, node.keyword);
+    if !node.opts.is_empty() {
+        head += &format!(" {{{}}}", node.opts.join(" "));
+    }
+
+    match node.body {
+        Body::Text(ref text) => lines.push(format!("{} {{{}}}", head, text)),
+        Body::Children(ref children) if children.is_empty() => {
+            lines.push(format!("{} {{}}", head));
+        }
+        Body::Children(ref children) => {
+            lines.push(format!("{} {{", head));
+            for child in children {
+                render(child, depth + 1, lines);
+            }
+
+            lines.push(format!("{}}}", indent));
+        }
+    }
+}
+
+// the text of `nodes` without their markup, whitespace and all.
+fn raw_text(nodes: &[Html]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Html::Text(text) => text.clone(),
+            Html::Element(el) => raw_text(&el.children),
+        })
+        .collect()
+}
+
+// whether a `pre` in `el` has line breaks or indentation, which vvsml text
+// cannot keep.
+fn has_formatted_pre(el: &Element) -> bool {
+    el.children.iter().any(|node| match node {
+        Html::Element(el) if el.name == "pre" => {
+            let text = raw_text(&el.children);
+            let text = text.trim_matches('\n');
+            text.contains('\n') || text.contains("  ") || text.starts_with(' ')
+        }
+        Html::Element(el) => has_formatted_pre(el),
+        Html::Text(_) => false,
+    })
+}
+
+// converts a markdown or html document into a vvsml source file, going by
+// the extension of `file_path`.
+pub fn import(file_path: &str, src: &str) -> String {
+    let extension = file_path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    let html = match extension.to_lowercase().as_str() {
+        "md" | "markdown" => {
+            let mut opts = Options::empty();
+            opts.insert(Options::ENABLE_TABLES);
+            opts.insert(Options::ENABLE_STRIKETHROUGH);
+
+            let mut html = String::new();
+            html::push_html(&mut html, Parser::new_ext(src, opts));
+            html
+        }
+        "html" | "htm" | "xhtml" => src.to_string(),
+        _ => {
+            let err_msg = "cannot import file, expected markdown or html";
+            error!(file_path, err_msg);
+            process::exit(-1);
+        }
+    };
+
+    let root = parse_html(&html);
+    if has_formatted_pre(&root) {
+        let warn_msg = "line breaks and indentation of preformatted text, \
+                        e.g. code blocks, are not kept";
+        warning!(file_path, warn_msg);
+    }
+
+    let contents = Node {
+        keyword: "contents",
+        opts: Vec::new(),
+        body: Body::Children(blocks(&root.children)),
+    };
+
+    let mut lines = Vec::new();
+    render(&contents, 0, &mut lines);
+    lines.join("\n") + "\n"
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ops::Range;
 use std::fmt::Display;
 use std::process;
@@ -116,7 +117,7 @@ pub fn expect_tok<'a, T: Logos<'a> + Display + PartialEq>(
     }
 }
 
-fn skip_block<'a, T: Logos<'a> + PartialEq + Copy>(
+pub fn skip_block<'a, T: Logos<'a> + PartialEq + Copy>(
     lex: &mut Lexer<'a, T>,
     block_start: T,
     block_end: T,
@@ -143,3 +144,68 @@ pub fn extract_arg<'a, T: Logos<'a> + Display + PartialEq + Copy>(
     let arg_end = lex.span().start;
     src[arg_start..arg_end].to_string()
 }
+
+// extracts an options block like `{key=value flag}`, if the next block is
+// followed by another one. options not in `allowed` are an error.
+pub fn options<'a, T: Logos<'a> + Display + PartialEq + Copy>(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<'a, T>,
+    block_start: T,
+    block_end: T,
+    allowed: &[&str],
+) -> HashMap<String, String>
+where
+    T::Extras: Clone,
+{
+    options_before(file_path, src, lex, block_start, block_end, allowed, 1)
+}
+
+// like `options`, for directives taking `args` blocks after the options.
+pub fn options_before<'a, T: Logos<'a> + Display + PartialEq + Copy>(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<'a, T>,
+    block_start: T,
+    block_end: T,
+    allowed: &[&str],
+    args: usize,
+) -> HashMap<String, String>
+where
+    T::Extras: Clone,
+{
+    let mut peek = lex.clone();
+    if peek.next() != Some(block_start) {
+        return HashMap::new();
+    }
+
+    let line = current_line(src, &peek);
+    for _ in 0..args {
+        skip_block(&mut peek, block_start, block_end);
+        if peek.next() != Some(block_start) {
+            return HashMap::new();
+        }
+    }
+
+    let text = extract_arg(file_path, src, lex, block_start, block_end);
+    let mut opts = HashMap::new();
+    for opt in text.split_whitespace() {
+        let (key, value) = opt.split_once('=').unwrap_or((opt, ""));
+        if !allowed.contains(&key) {
+            let err_msg = format!(
+                "invalid option: {}, expected one of {:?}",
+                key,
+                allowed,
+            );
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        }
+
+        if opts.insert(key.to_string(), value.to_string()).is_some() {
+            let warn_msg = format!("option {} is redundant", key);
+            warning!(file_path, line, warn_msg);
+        }
+    }
+
+    opts
+}
@@ -4,7 +4,43 @@ pub mod lang_util;
 pub mod special_ffmt;
 pub mod preproc;
 pub mod parse;
+pub mod resolve;
 pub mod code_gen;
+pub mod fmt;
+pub mod import;
+
+// with `check`, fails if any file is not formatted instead of formatting it.
+fn format_files(paths: &Vec<String>, check: bool) {
+    use std::fs;
+    use std::process;
+
+    let mut unformatted = false;
+    for path in paths {
+        let src = fs::read_to_string(path).unwrap_or_else(|e| {
+            error!(format!("unable to read source file {}: {}", path, e));
+            process::exit(-1);
+        });
+
+        let formatted = fmt::format(path, &src);
+        if formatted == src {
+            continue;
+        }
+
+        if check {
+            error!(path, "file is not formatted");
+            unformatted = true;
+        } else {
+            fs::write(path, formatted).unwrap_or_else(|e| {
+                error!(format!("unable to write {}: {}", path, e));
+                process::exit(-1);
+            });
+        }
+    }
+
+    if unformatted {
+        process::exit(-1);
+    }
+}
 
 fn main() {
     use std::fs::File;
@@ -12,26 +48,139 @@ fn main() {
     use std::fs;
     use std::env;
     use std::process;
+    use std::path::Path;
+
+    let usage = "usage: `vvsml [--format <fmt>] [--width <columns>] \
+                 [--pretty] [--json] [--include <dir>]... \
+                 <source file> <output file>`\n   \
+                 or: `vvsml ast [--json] [--include <dir>]... \
+                 <source file> <output file>`\n   \
+                 or: `vvsml fmt [--check] <source files>`\n   \
+                 or: `vvsml import <markdown or html file> <output file>`\n\
+                 the spans of `vvsml ast` are byte offsets into, and lines \
+                 of, the source after preprocessing.";
     
-    if env::args().len() != 3 {
-        error!("usage: `vvsml <source file> <output file>`");
+    let mut format = String::from("html");
+    let mut opts = code_gen::Options {
+        width: 80,
+        title: String::new(),
+        pretty: false,
+    };
+    
+    let mut paths = Vec::new();
+    let mut include_dirs = Vec::new();
+    let mut json = false;
+    let mut check = false;
+    let mut args = env::args().skip(1).peekable();
+
+    // `vvsml ast` writes out the parsed node tree instead of generating output,
+    // `vvsml fmt` formats source files in place and `vvsml import` converts
+    // markdown or html into a source file.
+    let mode = args.next_if(|arg| {
+        matches!(arg.as_str(), "ast" | "fmt" | "import")
+    });
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().unwrap_or_else(|| {
+                error!(usage);
+                process::exit(-1);
+            }),
+            "--width" => {
+                let width = args.next().unwrap_or_default();
+                opts.width = width.parse().unwrap_or_else(|_| {
+                    error!(format!("invalid width: {}", width));
+                    process::exit(-1);
+                });
+            }
+            "--include" => include_dirs.push(args.next().unwrap_or_else(|| {
+                error!(usage);
+                process::exit(-1);
+            })),
+            "--pretty" => opts.pretty = true,
+            "--json" => json = true,
+            "--check" => check = true,
+            _ => paths.push(arg),
+        }
+    }
+
+    if mode.as_deref() == Some("fmt") {
+        if paths.is_empty() {
+            error!(usage);
+            process::exit(-1);
+        }
+
+        format_files(&paths, check);
+        return;
+    }
+
+    if paths.len() != 2 {
+        error!(usage);
         process::exit(-1);
     }
 
-    let src_file = env::args().nth(1).unwrap();
-    let dst_file = env::args().nth(2).unwrap();
-    
-    let src = fs::read_to_string(&src_file).unwrap();
-    let src = preproc::preprocess(&src_file, &src);
-    let ast = parse::parse(&src_file, &src);
-    let html = code_gen::generate_html(&ast);
+    let src_file = &paths[0];
+    let dst_file = &paths[1];
+
+    // the title of a document is the name of its source file.
+    opts.title = Path::new(src_file)
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+
+    let backend = code_gen::backend(&format, &opts).unwrap_or_else(|| {
+        error!(format!("unknown output format: {}", format));
+        process::exit(-1);
+    });
+
+    let src = fs::read_to_string(src_file).unwrap_or_else(|e| {
+        error!(format!("unable to read source file: {}", e));
+        process::exit(-1);
+    });
+
+    let out = match mode.as_deref() {
+        Some("ast") => {
+            let src = preproc::preprocess(src_file, &src, &include_dirs);
+            let mut ast = parse::parse(src_file, &src);
+            match json {
+                true => {
+                    parse::deprotect_text(&mut ast);
+                    serde_json::to_string_pretty(&ast).unwrap() + "\n"
+                }
+                false => format!("{:#?}\n", ast),
+            }.into_bytes()
+        }
+        Some("import") => import::import(src_file, &src).into_bytes(),
+        _ => {
+            // a json node tree has already been parsed, and only needs its
+            // references resolved before code generation.
+            let mut ast = match json {
+                true => {
+                    let ast = serde_json::from_str(&src).unwrap_or_else(|e| {
+                        let err_msg = format!("invalid json node tree: {}", e);
+                        error!(src_file, err_msg);
+                        process::exit(-1);
+                    });
+
+                    parse::validate(src_file, &ast);
+                    ast
+                }
+                false => {
+                    let src =
+                        preproc::preprocess(src_file, &src, &include_dirs);
+                    parse::parse(src_file, &src)
+                }
+            };
+
+            resolve::resolve(src_file, &mut ast);
+            backend.generate(&ast)
+        }
+    };
 
-    File::create(&dst_file)
+    File::create(dst_file)
         .unwrap_or_else(|_| {
             error!("invalid output file");
             process::exit(-1);
         })
-        .write_all(&html.bytes().collect::<Vec<_>>())
+        .write_all(&out)
         .unwrap_or_else(|e| {
             error!(format!("unable to write output file: {}", e));
             process::exit(-1);
@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use std::process;
 use std::fmt::{Display, Debug, Formatter};
 use std::fmt;
 
 use logos::{Logos, Lexer};
+use serde::{Serialize, Deserialize};
 
 use crate::lang_util;
 
@@ -35,12 +37,30 @@ enum Token {
     #[token("row")]
     Row,
 
+    #[token("header_row")]
+    HeaderRow,
+
+    #[token("cell")]
+    Cell,
+
+    #[token("caption")]
+    Caption,
+
+    #[token("item")]
+    Item,
+
     #[token("{")]
     BlockStart,
 
     #[token("}")]
     BlockEnd,
 
+    #[token("[")]
+    AttrStart,
+
+    #[token("]")]
+    AttrEnd,
+
     #[error]
     #[regex(r"\s+", logos::skip)]
     Error,
@@ -58,8 +78,14 @@ impl Display for Token {
             Self::OrderedList => "ordered list",
             Self::Table => "table",
             Self::Row => "table row",
+            Self::HeaderRow => "table header row",
+            Self::Cell => "table cell",
+            Self::Caption => "table caption",
+            Self::Item => "list item",
             Self::BlockStart => "block start",
             Self::BlockEnd => "block end",
+            Self::AttrStart => "attribute block start",
+            Self::AttrEnd => "attribute block end",
             _ => "other",
         };
 
@@ -73,20 +99,190 @@ impl Debug for Token {
     }
 }
 
-#[derive(Debug)]
-pub enum Node {
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NumberStyle {
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+impl NumberStyle {
+    pub fn format(&self, num: i64) -> String {
+        let alpha = |num: i64, first| {
+            // bijective base 26, i.e. `a`, ..., `z`, `aa`, `ab`, ...
+            let mut num = num;
+            let mut digits = Vec::new();
+            while num > 0 {
+                num -= 1;
+                digits.push((first as u8 + (num % 26) as u8) as char);
+                num /= 26;
+            }
+
+            digits.into_iter().rev().collect::<String>()
+        };
+
+        let roman = |num: i64| {
+            let numerals = [
+                (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"),
+                (100, "c"), (90, "xc"), (50, "l"), (40, "xl"),
+                (10, "x"), (9, "ix"), (5, "v"), (4, "iv"),
+                (1, "i"),
+            ];
+            
+            let mut num = num;
+            let mut out = String::new();
+            for (value, numeral) in numerals {
+                while num >= value {
+                    out += numeral;
+                    num -= value;
+                }
+            }
+
+            out
+        };
+
+        // alphabetic and roman numbering cannot express non-positive numbers,
+        // and roman numerals past 3999 would need overlines.
+        if num <= 0 {
+            return num.to_string();
+        }
+        
+        match self {
+            Self::Decimal => num.to_string(),
+            Self::LowerAlpha => alpha(num, 'a'),
+            Self::UpperAlpha => alpha(num, 'A'),
+            Self::LowerRoman | Self::UpperRoman if num > 3999 => {
+                num.to_string()
+            }
+            Self::LowerRoman => roman(num),
+            Self::UpperRoman => roman(num).to_uppercase(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Numbering {
+    pub start: Option<i64>,
+    pub style: NumberStyle,
+    pub reversed: bool,
+}
+
+// the furthest from zero a list may start.
+pub const MAX_LIST_START: i64 = 1_000_000_000;
+
+impl Numbering {
+    // the number of the item at `index` in a list of `item_cnt` items.
+    pub fn number(&self, index: usize, item_cnt: usize) -> i64 {
+        let index = index as i64;
+        if self.reversed {
+            self.start.unwrap_or(item_cnt as i64).saturating_sub(index)
+        } else {
+            self.start.unwrap_or(1).saturating_add(index)
+        }
+    }
+}
+
+// the alignment of a table column. numeric columns are aligned on their
+// decimal points where a backend is able to, and to the right otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+    Numeric,
+}
+
+// html attributes attached to a node with `[id=... class=... data-...=...]`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Attributes {
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    pub data: Vec<(String, String)>,
+}
+
+// where a node is in the preprocessed source, as a byte range and the line it
+// starts on.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(flatten)]
+    pub kind: NodeKind,
+
+    // nodes written by hand, rather than exported, may leave these out.
+    #[serde(default)]
+    pub attrs: Attributes,
+    #[serde(default)]
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum NodeKind {
     Root(Vec<Box<Node>>),
     Contents(Vec<Box<Node>>),
-    Chapter(String),
-    Section(String),
-    Subsection(String),
-    Text(String),
+    Chapter(String, Option<String>),
+    Section(String, Option<String>),
+    Subsection(String, Option<String>),
+    Text(String, Option<String>),
     List(Vec<Box<Node>>),
-    OrderedList(Vec<Box<Node>>),
-    Table(Vec<Box<Node>>),
+    OrderedList(Vec<Box<Node>>, Numbering),
+
+    // a list item grouping several nodes.
+    Item(Vec<Box<Node>>, Option<String>),
+
+    // a table with the alignment of its first columns, the rest being left
+    // aligned.
+    Table(Vec<Box<Node>>, Option<String>, Vec<Align>),
     Row(Vec<Box<Node>>),
+    HeaderRow(Vec<Box<Node>>),
+
+    // a cell holding several nodes, with a column span and row span, and
+    // whether it is a header cell, e.g. in a header column.
+    Cell(Vec<Box<Node>>, usize, usize, bool),
+    Caption(String),
+}
+
+impl NodeKind {
+    pub fn label(&self) -> Option<&String> {
+        match self {
+            Self::Chapter(_, ref label)
+            | Self::Section(_, ref label)
+            | Self::Subsection(_, ref label)
+            | Self::Text(_, ref label)
+            | Self::Item(_, ref label)
+            | Self::Table(_, ref label, _) => label.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Box<Node>] {
+        match self {
+            Self::Root(ref children)
+            | Self::Contents(ref children)
+            | Self::List(ref children)
+            | Self::OrderedList(ref children, _)
+            | Self::Item(ref children, _)
+            | Self::Table(ref children, _, _)
+            | Self::Row(ref children)
+            | Self::HeaderRow(ref children)
+            | Self::Cell(ref children, ..) => children,
+            _ => &[],
+        }
+    }
 }
 
+type ParseFn = fn(&str, &str, &mut Lexer<Token>) -> NodeKind;
+
 macro_rules! parsing_rules {
     (($t:expr, $lex:expr, $file:expr, $src:expr); $($i:ident => $e:expr,)*) => {
         match $t {
@@ -103,7 +299,12 @@ macro_rules! parsing_rules {
 
 macro_rules! textual_extract_parse {
     ($fname:ident, $node_type:ident) => {
-        fn $fname(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> Node {
+        fn $fname(
+            file_path: &str,
+            src: &str,
+            lex: &mut Lexer<Token>,
+        ) -> NodeKind {
+            let opts = options(file_path, src, lex, &["label"]);
             let text = lang_util::extract_arg(
                 file_path,
                 src,
@@ -112,32 +313,166 @@ macro_rules! textual_extract_parse {
                 Token::BlockEnd,
             );
 
-            Node::$node_type(text)
+            NodeKind::$node_type(text, opts.get("label").cloned())
+        }
+    };
+}
+
+fn is_label(text: &str) -> bool {
+    lazy_regex! {
+        LABEL = r"^[A-Za-z0-9_\-]+$";
+    }
+
+    LABEL.is_match(text)
+}
+
+// an option block is an optional block of `key=value` pairs (or bare `key`
+// flags) preceding the main block of a node, e.g. `chapter {label=a} {A}`.
+// it is told apart from the main block by being followed by another block.
+fn options(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+    allowed: &[&str],
+) -> HashMap<String, String> {
+    let opts = lang_util::options(
+        file_path,
+        src,
+        lex,
+        Token::BlockStart,
+        Token::BlockEnd,
+        allowed,
+    );
+
+    if let Some(label) = opts.get("label") {
+        if !is_label(label) {
+            let err_msg = format!("invalid label: {}", label);
+            error!(file_path, lang_util::current_line(src, lex), err_msg);
+            process::exit(-1);
+        }
+    }
+
+    opts
+}
+
+fn attributes(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+) -> Attributes {
+    lazy_regex! {
+        ATTR = r#"^([^\s="]+)(=("[^"]*"|[^\s"]*))?"#;
+        ID = r"^[A-Za-z0-9_\-]+$";
+        DATA_KEY = r"^data-[a-z0-9\-]+$";
+    }
+
+    let mut peek = lex.clone();
+    if peek.next() != Some(Token::AttrStart) {
+        return Attributes::default();
+    }
+    
+    let line = lang_util::current_line(src, &peek);
+    let text = lang_util::extract_arg(
+        file_path,
+        src,
+        lex,
+        Token::AttrStart,
+        Token::AttrEnd,
+    );
+
+    let mut attrs = Attributes::default();
+    let mut text = text.trim_start();
+    while !text.is_empty() {
+        let caps = ATTR.captures(text).unwrap_or_else(|| {
+            let err_msg = format!("invalid attribute block: {}", text);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        });
+
+        let key = &caps[1];
+        let value = caps.get(3).map_or("", |value| value.as_str());
+        let value = value.trim_matches('"').to_string();
+        text = text[caps[0].len()..].trim_start();
+        
+        match key {
+            "id" if ID.is_match(&value) => {
+                if attrs.id.replace(value).is_some() {
+                    let warn_msg = "attribute id is redundant";
+                    warning!(file_path, line, warn_msg);
+                }
+            }
+            "class" => attrs.classes.extend(
+                value.split_whitespace().map(|class| class.to_string()),
+            ),
+            _ if DATA_KEY.is_match(key) => {
+                attrs.data.push((key.to_string(), value));
+            }
+            _ => {
+                let err_msg = format!("invalid attribute: {}={}", key, value);
+                error!(file_path, line, err_msg);
+                process::exit(-1);
+            }
         }
+    }
+
+    attrs
+}
+
+// parses a node with its attributes, `f` parses what follows the attributes.
+fn node(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+    f: ParseFn,
+) -> Node {
+    let start = lex.span().start;
+    let line = lang_util::current_line(src, lex);
+    let attrs = attributes(file_path, src, lex);
+    let kind = f(file_path, src, lex);
+    if attrs.id.is_some() && kind.label().is_some() {
+        let err_msg = "node has both a label and an id";
+        error!(file_path, line, err_msg);
+        process::exit(-1);
+    }
+
+    let span = Span {
+        start,
+        end: lex.span().end,
+        line,
     };
+
+    Node { kind, attrs, span }
 }
 
 macro_rules! layer_add_parse {
-    ($fname:ident, $node_type:ident) => {
-        fn $fname(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> Node {
+    ($fname:ident, $node_type:ident $(; $($i:ident => $f:ident),*)?) => {
+        fn $fname(
+            file_path: &str,
+            src: &str,
+            lex: &mut Lexer<Token>,
+        ) -> NodeKind {
             let mut children = Vec::new();
-            let mut add_child = |child| children.push(Box::new(child));
+            let mut add_child = |f: ParseFn, lex: &mut Lexer<Token>| {
+                children.push(Box::new(node(file_path, src, lex, f)));
+            };
+
             lang_util::expect_tok(file_path, src, lex, Token::BlockStart);
             while let Some(tok) = lex.next(){
                 parsing_rules! {
                     (tok, lex, file_path, src);
-                    Chapter => add_child(chapter(file_path, src, lex)),
-                    Section => add_child(section(file_path, src, lex)),
-                    Subsection => add_child(subsection(file_path, src, lex)),
-                    Text => add_child(text(file_path, src, lex)),
-                    List => add_child(list(file_path, src, lex)),
-                    OrderedList =>add_child(ordered_list(file_path, src, lex)),
-                    Table => add_child(table(file_path, src, lex)),
+                    Chapter => add_child(chapter, lex),
+                    Section => add_child(section, lex),
+                    Subsection => add_child(subsection, lex),
+                    Text => add_child(text, lex),
+                    List => add_child(list, lex),
+                    OrderedList =>add_child(ordered_list, lex),
+                    Table => add_child(table, lex),
+                    $($($i => add_child($f, lex),)*)?
                     BlockEnd => break,
                 }
             }
 
-            Node::$node_type(children)
+            NodeKind::$node_type(children)
         }
     };
 }
@@ -147,36 +482,373 @@ textual_extract_parse!(section, Section);
 textual_extract_parse!(subsection, Subsection);
 textual_extract_parse!(text, Text);
 
-layer_add_parse!(list, List);
-layer_add_parse!(ordered_list, OrderedList);
-layer_add_parse!(row, Row);
+layer_add_parse!(list, List; Item => item);
+layer_add_parse!(row, Row; Cell => cell);
+layer_add_parse!(header_row, HeaderRow; Cell => cell);
 layer_add_parse!(contents, Contents);
 
-fn table(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> Node {
+fn ordered_list(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+) -> NodeKind {
+    let opts = options(file_path, src, lex, &["start", "style", "reversed"]);
+    let line = lang_util::current_line(src, lex);
+    let start = opts.get("start").map(|start| {
+        match start.parse::<i64>() {
+            Ok(num) if num.abs() <= MAX_LIST_START => num,
+            _ => {
+                let err_msg = format!("invalid list start: {}", start);
+                error!(file_path, line, err_msg);
+                process::exit(-1);
+            }
+        }
+    });
+
+    let style = match opts.get("style").map(|style| style.as_str()) {
+        Some("decimal") | None => NumberStyle::Decimal,
+        Some("lower-alpha") => NumberStyle::LowerAlpha,
+        Some("upper-alpha") => NumberStyle::UpperAlpha,
+        Some("lower-roman") => NumberStyle::LowerRoman,
+        Some("upper-roman") => NumberStyle::UpperRoman,
+        Some(style) => {
+            let err_msg = format!("invalid numbering style: {}", style);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        }
+    };
+
+    let numbering = Numbering {
+        start,
+        style,
+        reversed: opts.contains_key("reversed"),
+    };
+
+    // ordered lists accept the same children as unordered lists.
+    match list(file_path, src, lex) {
+        NodeKind::List(children) => NodeKind::OrderedList(children, numbering),
+        _ => unreachable!(),
+    }
+}
+
+fn table(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> NodeKind {
+    let opts = options(file_path, src, lex, &["label", "align"]);
+    let line = lang_util::current_line(src, lex);
+
+    // e.g. `align=left,numeric`, for the first two columns.
+    let align = opts.get("align").map_or(Vec::new(), |align| {
+        align
+            .split(',')
+            .map(|col| match col {
+                "left" => Align::Left,
+                "right" => Align::Right,
+                "center" => Align::Center,
+                "numeric" => Align::Numeric,
+                _ => {
+                    let err_msg = format!("invalid column alignment: {}", col);
+                    error!(file_path, line, err_msg);
+                    process::exit(-1);
+                }
+            })
+            .collect()
+    });
+
     let mut children = Vec::new();
-    let mut add_child = |child| children.push(Box::new(child));
+    let mut add_child = |f: ParseFn, lex: &mut Lexer<Token>| {
+        children.push(Box::new(node(file_path, src, lex, f)));
+    };
+
     lang_util::expect_tok(file_path, src, lex, Token::BlockStart);
     while let Some(tok) = lex.next(){
         parsing_rules! {
             (tok, lex, file_path, src);
-            Row => add_child(row(file_path, src, lex)),
+            Row => add_child(row, lex),
+            HeaderRow => add_child(header_row, lex),
+            Caption => add_child(caption, lex),
             BlockEnd => break,
         }
     }
 
-    Node::Table(children)
+    let caption_cnt = children
+        .iter()
+        .filter(|child| matches!(child.kind, NodeKind::Caption(..)))
+        .count();
+    
+    if caption_cnt > 1 {
+        let err_msg = "table has more than one caption";
+        error!(file_path, lang_util::current_line(src, lex), err_msg);
+        process::exit(-1);
+    }
+
+    NodeKind::Table(children, opts.get("label").cloned(), align)
+}
+
+fn caption(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> NodeKind {
+    NodeKind::Caption(lang_util::extract_arg(
+        file_path,
+        src,
+        lex,
+        Token::BlockStart,
+        Token::BlockEnd,
+    ))
+}
+
+fn span(
+    file_path: &str,
+    line: usize,
+    opts: &HashMap<String, String>,
+    key: &str,
+) -> usize {
+    match opts.get(key) {
+        Some(value) => match value.parse::<usize>() {
+            Ok(span) if span > 0 => span,
+            _ => {
+                let err_msg = format!("invalid {}: {}", key, value);
+                error!(file_path, line, err_msg);
+                process::exit(-1);
+            }
+        }
+        None => 1,
+    }
+}
+
+fn cell(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> NodeKind {
+    let allowed = ["colspan", "rowspan", "header"];
+    let opts = options(file_path, src, lex, &allowed);
+    let line = lang_util::current_line(src, lex);
+
+    // cells accept the same children as `contents`.
+    match contents(file_path, src, lex) {
+        NodeKind::Contents(children) => NodeKind::Cell(
+            children,
+            span(file_path, line, &opts, "colspan"),
+            span(file_path, line, &opts, "rowspan"),
+            opts.contains_key("header"),
+        ),
+        _ => unreachable!(),
+    }
+}
+
+fn item(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> NodeKind {
+    let opts = options(file_path, src, lex, &["label"]);
+
+    // items accept the same children as `contents`.
+    match contents(file_path, src, lex) {
+        NodeKind::Contents(children) => {
+            NodeKind::Item(children, opts.get("label").cloned())
+        }
+        _ => unreachable!(),
+    }
 }
 
 pub fn parse(file_path: &str, src: &str) -> Node {
     let mut children = Vec::new();
     let mut lex = Token::lexer(src);
-    let mut add_child = |child| children.push(Box::new(child));
+    let mut add_child = |f: ParseFn, lex: &mut Lexer<Token>| {
+        children.push(Box::new(node(file_path, src, lex, f)));
+    };
     while let Some(tok) = lex.next() {
         parsing_rules! {
             (tok, &lex, file_path, src);
-            Contents => add_child(contents(file_path, src, &mut lex)),
+            Contents => add_child(contents, &mut lex),
         }
     }
 
-    Node::Root(children)
+    Node {
+        kind: NodeKind::Root(children),
+        attrs: Attributes::default(),
+        span: Span { start: 0, end: src.len(), line: 1 },
+    }
+}
+
+// fails with an error on the line of a node, if it has one. nodes written by
+// hand may have no span to point at.
+pub fn node_error(file_path: &str, line: usize, msg: String) -> ! {
+    match line {
+        0 => error!(file_path, msg),
+        line => error!(file_path, line, msg),
+    }
+
+    process::exit(-1);
+}
+
+fn invalid_node(file_path: &str, node: &Node, msg: String) -> ! {
+    node_error(file_path, node.span.line, msg)
+}
+
+fn kind_name(kind: &NodeKind) -> &'static str {
+    match kind {
+        NodeKind::Root(_) => "root",
+        NodeKind::Contents(_) => "contents",
+        NodeKind::Chapter(..) => "chapter",
+        NodeKind::Section(..) => "section",
+        NodeKind::Subsection(..) => "subsection",
+        NodeKind::Text(..) => "text",
+        NodeKind::List(_) => "list",
+        NodeKind::OrderedList(..) => "ordered list",
+        NodeKind::Item(..) => "list item",
+        NodeKind::Table(..) => "table",
+        NodeKind::Row(_) => "table row",
+        NodeKind::HeaderRow(_) => "table header row",
+        NodeKind::Cell(..) => "table cell",
+        NodeKind::Caption(_) => "table caption",
+    }
+}
+
+fn validate_node(file_path: &str, node: &Node) {
+    let is_block = |kind: &NodeKind| matches!(
+        kind,
+        NodeKind::Chapter(..)
+        | NodeKind::Section(..)
+        | NodeKind::Subsection(..)
+        | NodeKind::Text(..)
+        | NodeKind::List(_)
+        | NodeKind::OrderedList(..)
+        | NodeKind::Table(..),
+    );
+
+    if let Some(label) = node.kind.label() {
+        if !is_label(label) {
+            invalid_node(file_path, node, format!("invalid label: {}", label));
+        }
+
+        if node.attrs.id.is_some() {
+            let err_msg = "node has both a label and an id".to_string();
+            invalid_node(file_path, node, err_msg);
+        }
+    }
+
+    if let Some(id) = &node.attrs.id {
+        if !is_label(id) {
+            let err_msg = format!("invalid attribute: id={}", id);
+            invalid_node(file_path, node, err_msg);
+        }
+    }
+
+    match node.kind {
+        NodeKind::Cell(_, colspan, _, _) if colspan == 0 => {
+            let err_msg = format!("invalid colspan: {}", colspan);
+            invalid_node(file_path, node, err_msg);
+        }
+        NodeKind::Cell(_, _, rowspan, _) if rowspan == 0 => {
+            let err_msg = format!("invalid rowspan: {}", rowspan);
+            invalid_node(file_path, node, err_msg);
+        }
+        NodeKind::OrderedList(_, Numbering { start: Some(start), .. })
+            if start.abs() > MAX_LIST_START =>
+        {
+            let err_msg = format!("invalid list start: {}", start);
+            invalid_node(file_path, node, err_msg);
+        }
+        NodeKind::Table(ref children, ..) => {
+            let caption_cnt = children
+                .iter()
+                .filter(|child| matches!(child.kind, NodeKind::Caption(..)))
+                .count();
+
+            if caption_cnt > 1 {
+                let err_msg = "table has more than one caption".to_string();
+                invalid_node(file_path, node, err_msg);
+            }
+        }
+        _ => {}
+    }
+
+    for child in node.kind.children() {
+        let kind = &child.kind;
+        let allowed = match node.kind {
+            NodeKind::Root(_) => matches!(kind, NodeKind::Contents(_)),
+            NodeKind::List(_) | NodeKind::OrderedList(..) => {
+                is_block(kind) || matches!(kind, NodeKind::Item(..))
+            }
+            NodeKind::Table(..) => matches!(
+                kind,
+                NodeKind::Row(_)
+                | NodeKind::HeaderRow(_)
+                | NodeKind::Caption(_),
+            ),
+            NodeKind::Row(_) | NodeKind::HeaderRow(_) => {
+                is_block(kind) || matches!(kind, NodeKind::Cell(..))
+            }
+            _ => is_block(kind),
+        };
+
+        if !allowed {
+            let err_msg = format!(
+                "{} cannot appear in {}",
+                kind_name(kind),
+                kind_name(&node.kind),
+            );
+
+            invalid_node(file_path, child, err_msg);
+        }
+
+        validate_node(file_path, child);
+    }
+}
+
+// checks a node tree that was not produced by `parse`, e.g. one read from
+// json, against the rules that `parse` enforces while parsing.
+pub fn validate(file_path: &str, root: &Node) {
+    if !matches!(root.kind, NodeKind::Root(_)) {
+        let err_msg = format!("expected root, found {}", kind_name(&root.kind));
+        invalid_node(file_path, root, err_msg);
+    }
+
+    validate_node(file_path, root);
+}
+
+// rewrites the text of a node and of everything in it with `f`.
+fn map_text(node: &mut Node, f: &dyn Fn(&str) -> String) {
+    match node.kind {
+        NodeKind::Chapter(ref mut data, _)
+        | NodeKind::Section(ref mut data, _)
+        | NodeKind::Subsection(ref mut data, _)
+        | NodeKind::Text(ref mut data, _)
+        | NodeKind::Caption(ref mut data) => *data = f(data),
+        NodeKind::Root(ref mut children)
+        | NodeKind::Contents(ref mut children)
+        | NodeKind::List(ref mut children)
+        | NodeKind::OrderedList(ref mut children, _)
+        | NodeKind::Item(ref mut children, _)
+        | NodeKind::Table(ref mut children, _, _)
+        | NodeKind::Row(ref mut children)
+        | NodeKind::HeaderRow(ref mut children)
+        | NodeKind::Cell(ref mut children, ..) => {
+            for child in children {
+                map_text(child, f);
+            }
+        }
+    }
+}
+
+// json node trees hold the characters that the protected sequences of the
+// preprocessor stand for, so that scripts do not need to know about them. a
+// protected `.` or `@` that would be read back in as a reference or another
+// protected sequence stays protected.
+pub fn deprotect_text(node: &mut Node) {
+    lazy_regex! {
+        PROTECTED_SEQ = r"@#':\[;:([A-Z][A-Z0-9_])\](ref|#':\[)?";
+    }
+
+    map_text(node, &|text| {
+        PROTECTED_SEQ
+            .replace_all(text, |caps: &regex::Captures| {
+                let next = caps.get(2).map_or("", |next| next.as_str());
+                let ch = match (&caps[1], next) {
+                    ("P_", "ref") | ("A_", "#':[") => {
+                        return caps[0].to_string();
+                    }
+                    ("LB", _) => "{",
+                    ("RB", _) => "}",
+                    ("EC", _) => "]",
+                    ("P_", _) => ".",
+                    ("A_", _) => "@",
+                    _ => return caps[0].to_string(),
+                };
+
+                format!("{}{}", ch, next)
+            })
+            .to_string()
+    });
 }
@@ -3,6 +3,7 @@ use std::process;
 use std::fmt;
 use std::fs;
 use std::fmt::{Formatter, Display};
+use std::path::Path;
 
 use logos::{Logos, Lexer};
 use regex::Regex;
@@ -36,6 +37,21 @@ enum Token {
     #[token(".external_table")]
     ExternalTable,
 
+    #[token(".external_csv")]
+    ExternalCsv,
+
+    #[token(".external_tsv")]
+    ExternalTsv,
+
+    #[token(".external_json_table")]
+    ExternalJsonTable,
+
+    #[token(".for_each_row")]
+    ForEachRow,
+
+    #[token(".vvtab")]
+    Vvtab,
+
     #[token("{")]
     BlockStart,
 
@@ -57,6 +73,11 @@ impl Display for Token {
             Self::Unicode => "unicode codepoint",
             Self::ReplaceAll => "regex replacement",
             Self::ExternalTable => "external text table",
+            Self::ExternalCsv => "external csv table",
+            Self::ExternalTsv => "external tsv table",
+            Self::ExternalJsonTable => "external json table",
+            Self::ForEachRow => "row template",
+            Self::Vvtab => "inline text table",
             Self::BlockStart => "block start",
             Self::BlockEnd => "block end",
             _ => "other",
@@ -258,33 +279,384 @@ fn replace_all(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> String {
     regex.replace_all(&src, &replacement).to_string()
 }
 
+// e.g. `{sort=2 desc numeric filter=1:^a columns=2,1 limit=10}`, where
+// columns are counted from 1.
+fn row_selection(
+    file_path: &str,
+    line: usize,
+    opts: &HashMap<String, String>,
+) -> special_ffmt::RowSelection {
+    let column = |col: &str| match col.trim().parse::<usize>() {
+        Ok(col) if col > 0 => col - 1,
+        _ => {
+            let err_msg = format!("invalid column: {}", col);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        }
+    };
+
+    let filter = opts.get("filter").map(|filter| {
+        let (col, regex) = filter.split_once(':').unwrap_or_else(|| {
+            let err_msg = format!("expected column:regex, got {}", filter);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        });
+
+        let regex = Regex::new(regex).unwrap_or_else(|_| {
+            let err_msg = format!("invalid regex: {}", regex);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        });
+
+        (column(col), regex)
+    });
+
+    let limit = opts.get("limit").map(|limit| {
+        limit.parse::<usize>().unwrap_or_else(|_| {
+            let err_msg = format!("invalid row limit: {}", limit);
+            error!(file_path, line, err_msg);
+            process::exit(-1);
+        })
+    });
+
+    for flag in ["desc", "numeric"] {
+        if opts.contains_key(flag) && !opts.contains_key("sort") {
+            let warn_msg =
+                format!("option {} has no effect without sort", flag);
+            warning!(file_path, line, warn_msg);
+        }
+    }
+
+    special_ffmt::RowSelection {
+        sort: opts.get("sort").map(|col| column(col)),
+        descending: opts.contains_key("desc"),
+        numeric: opts.contains_key("numeric"),
+        filter,
+        columns: opts
+            .get("columns")
+            .map(|cols| cols.split(',').map(column).collect()),
+        limit,
+    }
+}
+
+// reads a file included by `file_path`, looking for it next to `file_path`
+// first and then in each of `include_dirs`, and returns where it was found
+// along with its contents. absolute paths are only looked for as they are.
+fn read_external(
+    file_path: &str,
+    line: usize,
+    path: &str,
+    include_dirs: &[String],
+) -> (String, String) {
+    let source_dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+    let dirs = [source_dir]
+        .into_iter()
+        .chain(include_dirs.iter().map(Path::new));
+
+    let mut tried = Vec::new();
+    for dir in dirs {
+        let candidate = dir.join(path);
+        if candidate.is_file() {
+            let candidate = candidate.to_string_lossy().to_string();
+            let conts = fs::read_to_string(&candidate).unwrap_or_else(|e| {
+                let err_msg = format!("unable to read {}: {}", candidate, e);
+                error!(file_path, line, err_msg);
+                process::exit(-1);
+            });
+
+            return (candidate, conts);
+        }
+
+        tried.push(candidate.to_string_lossy().to_string());
+        if Path::new(path).is_absolute() {
+            break;
+        }
+    }
+
+    let err_msg = format!(
+        "external file not found: {}, tried {}",
+        path,
+        tried.join(", "),
+    );
+
+    error!(file_path, line, err_msg);
+    process::exit(-1);
+}
+
 fn external_table(
     file_path: &str,
     src: &str,
     lex: &mut Lexer<Token>,
+    include_dirs: &[String],
 ) -> String {
     let extab_start = lex.span().start;
+    let line = lang_util::current_line(src, lex);
+    let opts = lang_util::options(
+        file_path,
+        src,
+        lex,
+        Token::BlockStart,
+        Token::BlockEnd,
+        &["sort", "desc", "numeric", "filter", "columns", "limit"],
+    );
+
+    let selection = row_selection(file_path, line, &opts);
     let extab_path = extract_arg(file_path, src, lex);
     let extab_end = lex.span().end;
+    let (extab_path, extab_src) = read_external(
+        file_path,
+        lang_util::current_line(src, lex),
+        &extab_path,
+        include_dirs,
+    );
 
-    let extab_src = fs::read_to_string(&extab_path).unwrap_or_else(|_| {
-        let err_msg = format!("bad external text table path: {}", extab_path);
-        error!(file_path, lang_util::current_line(src, lex), err_msg);
+    let mut src = src.to_string();
+    let replacement = special_ffmt::vvtab_to_vvsml(
+        &extab_path,
+        1,
+        1,
+        &extab_src,
+        &selection,
+    );
+
+    src.replace_range(extab_start..extab_end, &replacement);
+
+    src
+}
+
+// a text table written in the source itself, e.g. `.vvtab {a & b $ c & d}`.
+fn vvtab(file_path: &str, src: &str, lex: &mut Lexer<Token>) -> String {
+    let tab_start = lex.span().start;
+    let mut peek = lex.clone();
+    peek.next();
+    let first_line = lang_util::current_line(src, &peek);
+
+    // the table starts right after the `{`, partway through its first line.
+    let line_start = src[..peek.span().end].rfind('\n').map_or(0, |i| i + 1);
+    let first_column = 1 + src[line_start..peek.span().end].chars().count();
+
+    let tab_src = extract_arg(file_path, src, lex);
+    let tab_end = lex.span().end;
+
+    let mut src = src.to_string();
+    let replacement = special_ffmt::vvtab_to_vvsml(
+        file_path,
+        first_line,
+        first_column,
+        &tab_src,
+        &special_ffmt::RowSelection::default(),
+    );
+
+    src.replace_range(tab_start..tab_end, &replacement);
+    src
+}
+
+// csv and tsv tables, which only differ in the character between fields.
+fn external_dsv(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+    delimiter: char,
+    include_dirs: &[String],
+) -> String {
+    let extab_start = lex.span().start;
+    let opts = lang_util::options(
+        file_path,
+        src,
+        lex,
+        Token::BlockStart,
+        Token::BlockEnd,
+        &["header"],
+    );
+
+    let extab_path = extract_arg(file_path, src, lex);
+    let extab_end = lex.span().end;
+    let (extab_path, extab_src) = read_external(
+        file_path,
+        lang_util::current_line(src, lex),
+        &extab_path,
+        include_dirs,
+    );
+
+    let header = opts.contains_key("header");
+    let replacement = special_ffmt::dsv_to_vvsml(
+        &extab_path,
+        &extab_src,
+        delimiter,
+        header,
+    );
+
+    let mut src = src.to_string();
+    src.replace_range(extab_start..extab_end, &replacement);
+    src
+}
+
+// an array of json objects, e.g. `.external_json_table {path} {name,age}`,
+// with a column for each of the listed keys.
+fn external_json_table(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+    include_dirs: &[String],
+) -> String {
+    let extab_start = lex.span().start;
+    let extab_path = extract_arg(file_path, src, lex);
+    let keys = extract_arg(file_path, src, lex);
+    let extab_end = lex.span().end;
+    let line = lang_util::current_line(src, lex);
+
+    let keys = keys.split(',').map(str::trim).collect::<Vec<_>>();
+    if keys.iter().any(|key| key.is_empty()) {
+        error!(file_path, line, "expected a comma separated list of keys");
         process::exit(-1);
-    });
+    }
+
+    let (extab_path, extab_src) = read_external(
+        file_path,
+        line,
+        &extab_path,
+        include_dirs,
+    );
+
+    let replacement = special_ffmt::json_to_vvsml(
+        &extab_path,
+        &extab_src,
+        &keys,
+    );
 
     let mut src = src.to_string();
-    let replacement = special_ffmt::vvtab_to_vvsml(&extab_src);
     src.replace_range(extab_start..extab_end, &replacement);
+    src
+}
 
+// fills in the references to columns in a template with the cells of a row.
+// columns are referred to as `$1`, by name as `$name` or `${column name}`,
+// and `$$` is a `$`.
+fn fill_template(
+    file_path: &str,
+    first_line: usize,
+    template: &str,
+    names: &[String],
+    width: usize,
+    row: &[String],
+) -> String {
+    lazy_regex! {
+        COLUMN_REF = r"\$(\$|[0-9]+|[A-Za-z_][A-Za-z0-9_]*|\{[^{}]*\})";
+    }
+
+    COLUMN_REF
+        .replace_all(template, |caps: &regex::Captures| {
+            if &caps[1] == "$" {
+                return String::from("$");
+            }
+
+            let col_ref = caps[1].trim_start_matches('{').trim_end_matches('}');
+            let col = match col_ref.parse::<usize>() {
+                Ok(col) => col.checked_sub(1),
+                Err(_) => names.iter().position(|name| name == col_ref),
+            };
+
+            let line = first_line
+                + template.count_lines_in(0..caps.get(0).unwrap().start());
+
+            match col {
+                Some(col) if col < width => {
+                    row.get(col).cloned().unwrap_or_default()
+                }
+                _ => {
+                    let err_msg = format!("unknown column: {}", col_ref);
+                    error!(file_path, line, err_msg);
+                    process::exit(-1);
+                }
+            }
+        })
+        .to_string()
+}
+
+// repeats a template for each row of a vvtab, csv or tsv file, e.g.
+// `.for_each_row {header} {path} {section {$name} text {$2}}`.
+fn for_each_row(
+    file_path: &str,
+    src: &str,
+    lex: &mut Lexer<Token>,
+    include_dirs: &[String],
+) -> String {
+    let for_start = lex.span().start;
+    let opts = lang_util::options_before(
+        file_path,
+        src,
+        lex,
+        Token::BlockStart,
+        Token::BlockEnd,
+        &["header"],
+        2,
+    );
+
+    let data_path = extract_arg(file_path, src, lex);
+    let line = lang_util::current_line(src, lex);
+    let mut peek = lex.clone();
+    peek.next();
+    let template_line = lang_util::current_line(src, &peek);
+
+    let template = extract_arg(file_path, src, lex);
+    let for_end = lex.span().end;
+
+    let (data_path, data_src) = read_external(
+        file_path,
+        line,
+        &data_path,
+        include_dirs,
+    );
+
+    let header = opts.contains_key("header");
+    let (names, rows) = special_ffmt::data_rows(&data_path, &data_src, header);
+
+    // the header, or the first row without one, tells how many columns a
+    // numeric reference may refer to.
+    let width = match names.is_empty() {
+        true => rows.first().map_or(0, Vec::len),
+        false => names.len(),
+    };
+
+    let replacement = rows
+        .iter()
+        .map(|row| {
+            fill_template(
+                file_path,
+                template_line,
+                &template,
+                &names,
+                width,
+                row,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut src = src.to_string();
+    src.replace_range(for_start..for_end, &replacement);
     src
 }
 
-pub fn preprocess(file_path: &str, src: &str) -> String {
+// external files are looked for next to `file_path`, then in `include_dirs`.
+pub fn preprocess(
+    file_path: &str,
+    src: &str,
+    include_dirs: &[String],
+) -> String {
     let mut src = protect_seqs(file_path, src);
     let mut lex = Token::lexer(&src);
     let mut sym_tab = HashMap::new();
     while let Some(tok) = lex.next() {
+        // `.vvtab` is also a file extension, so without a block it is only
+        // text, e.g. a file called `data.vvtab` mentioned in a sentence.
+        let is_text = tok == Token::Vvtab
+            && lex.clone().next() != Some(Token::BlockStart);
+
+        if is_text {
+            continue;
+        }
+
         src = match tok {
             Token::DefineMacro => define_macro(
                 file_path,
@@ -297,7 +669,22 @@ pub fn preprocess(file_path: &str, src: &str) -> String {
             Token::Link => link(file_path, &src, &mut lex),
             Token::Unicode => unicode(file_path, &src, &mut lex),
             Token::ReplaceAll => replace_all(file_path, &src, &mut lex),
-            Token::ExternalTable => external_table(file_path, &src, &mut lex),
+            Token::ExternalTable => {
+                external_table(file_path, &src, &mut lex, include_dirs)
+            }
+            Token::ExternalCsv => {
+                external_dsv(file_path, &src, &mut lex, ',', include_dirs)
+            }
+            Token::ExternalTsv => {
+                external_dsv(file_path, &src, &mut lex, '\t', include_dirs)
+            }
+            Token::ExternalJsonTable => {
+                external_json_table(file_path, &src, &mut lex, include_dirs)
+            }
+            Token::ForEachRow => {
+                for_each_row(file_path, &src, &mut lex, include_dirs)
+            }
+            Token::Vvtab => vvtab(file_path, &src, &mut lex),
             _ => continue,
         };
 
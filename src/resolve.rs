@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::process;
+
+use crate::parse::{self, Node, NodeKind};
+use crate::lang_util::FindRev;
+
+// `item_num` is the number of `node` in its parent list, if it is a list item.
+// list items are referred to by their number, tables by their position in the
+// document and headings by their title.
+fn collect_labels(
+    file_path: &str,
+    node: &Node,
+    item_num: Option<String>,
+    table_cnt: &mut usize,
+    labels: &mut HashMap<String, String>,
+) {
+    let line = node.span.line;
+    let mut add_label = |label: &Option<String>, title: String| {
+        if let Some(label) = label {
+            if labels.insert(label.clone(), title).is_some() {
+                let err_msg = format!("duplicate label: {}", label);
+                parse::node_error(file_path, line, err_msg);
+            }
+        }
+    };
+
+    match node.kind {
+        NodeKind::Chapter(ref data, ref label)
+        | NodeKind::Section(ref data, ref label)
+        | NodeKind::Subsection(ref data, ref label) => {
+            add_label(label, data.trim().to_string());
+        }
+        NodeKind::Caption(_) => {}
+        NodeKind::Text(_, ref label) => match item_num {
+            Some(num) => add_label(label, num),
+            None => if let Some(label) = label {
+                let err_msg = format!(
+                    "label {} is not on a heading, table or list item",
+                    label,
+                );
+                parse::node_error(file_path, line, err_msg);
+            }
+        }
+        NodeKind::Item(ref children, ref label) => {
+            if let Some(num) = item_num {
+                add_label(label, num);
+            }
+
+            for child in children {
+                collect_labels(file_path, child, None, table_cnt, labels);
+            }
+        }
+        NodeKind::Table(ref children, ref label, _) => {
+            *table_cnt += 1;
+            add_label(label, table_cnt.to_string());
+            for child in children {
+                collect_labels(file_path, child, None, table_cnt, labels);
+            }
+        }
+        NodeKind::List(ref children) => {
+            for (i, child) in children.iter().enumerate() {
+                let item_num = Some((i + 1).to_string());
+                collect_labels(file_path, child, item_num, table_cnt, labels);
+            }
+        }
+        NodeKind::OrderedList(ref children, ref numbering) => {
+            for (i, child) in children.iter().enumerate() {
+                let num = numbering.number(i, children.len());
+                let item_num = Some(numbering.style.format(num));
+                collect_labels(file_path, child, item_num, table_cnt, labels);
+            }
+        }
+        NodeKind::Root(ref children)
+        | NodeKind::Contents(ref children)
+        | NodeKind::Row(ref children)
+        | NodeKind::HeaderRow(ref children)
+        | NodeKind::Cell(ref children, ..) => {
+            for child in children {
+                collect_labels(file_path, child, None, table_cnt, labels);
+            }
+        }
+    }
+}
+
+// `line` is the line of the node that `text` belongs to.
+fn resolve_refs(
+    file_path: &str,
+    line: usize,
+    text: &mut String,
+    labels: &HashMap<String, String>,
+) {
+    lazy_regex! {
+        REF = r"\.ref\s*\{([^{}]*)\}";
+    }
+
+    for mat in REF.find_rev(&text.clone()) {
+        let label = REF.captures(mat.as_str()).unwrap()[1].trim().to_string();
+        let title = labels.get(&label).unwrap_or_else(|| {
+            let err_msg = format!("reference to undefined label: {}", label);
+            parse::node_error(file_path, line, err_msg);
+        });
+
+        text.replace_range(
+            mat.range(),
+            &format!("<a href=\"#{}\">{}</a>", label, title),
+        );
+    }
+}
+
+fn resolve_node(
+    file_path: &str,
+    node: &mut Node,
+    labels: &HashMap<String, String>,
+) {
+    let line = node.span.line;
+    match node.kind {
+        NodeKind::Chapter(ref mut data, _)
+        | NodeKind::Section(ref mut data, _)
+        | NodeKind::Subsection(ref mut data, _)
+        | NodeKind::Text(ref mut data, _)
+        | NodeKind::Caption(ref mut data) => {
+            resolve_refs(file_path, line, data, labels);
+        }
+        NodeKind::Root(ref mut children)
+        | NodeKind::Contents(ref mut children)
+        | NodeKind::List(ref mut children)
+        | NodeKind::OrderedList(ref mut children, _)
+        | NodeKind::Item(ref mut children, _)
+        | NodeKind::Table(ref mut children, _, _)
+        | NodeKind::Row(ref mut children)
+        | NodeKind::HeaderRow(ref mut children)
+        | NodeKind::Cell(ref mut children, ..) => {
+            for child in children {
+                resolve_node(file_path, child, labels);
+            }
+        }
+    }
+}
+
+// resolves `.ref {label}` references to the headings, tables and list items
+// labeled with `{label=...}`, turning them into links.
+pub fn resolve(file_path: &str, root: &mut Node) {
+    if !matches!(root.kind, NodeKind::Root(_)) {
+        error!("tried to resolve references in non-root node");
+        process::exit(-1);
+    }
+
+    let mut labels = HashMap::new();
+    collect_labels(file_path, root, None, &mut 0, &mut labels);
+    resolve_node(file_path, root, &labels);
+}
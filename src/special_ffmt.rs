@@ -1,7 +1,11 @@
+use std::cmp::Ordering;
+use std::process;
+
 use logos::Logos;
+use regex::Regex;
 
 use crate::lazy_regex;
-use crate::lang_util::FindRev;
+use crate::lang_util::CountLines;
 
 #[derive(Logos)]
 enum VvtabToken {
@@ -10,93 +14,662 @@ enum VvtabToken {
     
     #[token("$")]
     NextRow,
+
+    // note that `\` is used as the escape character, but only for `&` and `$`.
+    // i.e. `\$` or `\&` are escaped, but `\^` is not.
+    #[regex(r"\\[&$]")]
+    Escape,
+
+    #[regex(r"@#':\[;:[A-Z][A-Z0-9_]\]")]
+    ProtectedSeq,
     
     #[error]
     Error,
 }
 
-pub fn vvtab_to_vvsml(src: &str) -> String {
+// the line and column of a byte offset into `src`, which starts at
+// `first_column` of `first_line`, as `line:column`.
+fn position(
+    src: &str,
+    first_line: usize,
+    first_column: usize,
+    offset: usize,
+) -> String {
+    let before = &src[..offset];
+    let line = first_line + before.count_lines();
+    let col = match before.rfind('\n') {
+        Some(newline) => 1 + before[(newline + 1)..].chars().count(),
+        None => first_column + before.chars().count(),
+    };
+
+    format!("{}:{}", line, col)
+}
+
+fn fail(file_path: &str, pos: String, msg: &str) -> ! {
+    error!(file_path, pos, msg);
+    process::exit(-1);
+}
+
+// how a vvtab table is laid out, given on `#` lines before its data, e.g.
+//
+//     #header_rows 1
+//     #header_columns 1
+//     #align left numeric numeric
+//     #footer total sum sum
+//     #footer average mean mean
+//     #decimals 2
+//     #thousands_separator ,
+//
+// other lines starting with `#` are data, so that cells may start with one.
+struct VvtabLayout<'a> {
+    header_rows: usize,
+    header_columns: usize,
+    align: Vec<&'a str>,
+
+    // a footer cell is either an aggregate of the numbers in its column, `-`
+    // for an empty cell, or text.
+    footers: Vec<Vec<&'a str>>,
+
+    // by default, aggregates have as many decimals as the numbers in their
+    // column, and means at least two.
+    decimals: Option<usize>,
+    thousands_separator: &'a str,
+}
+
+// reads the layout lines at the start of `src`, returning the layout and the
+// offset that the data starts at.
+fn vvtab_layout<'a>(
+    file_path: &str,
+    first_line: usize,
+    first_column: usize,
+    src: &'a str,
+) -> (VvtabLayout<'a>, usize) {
     lazy_regex! {
-        WHITESPACE = r"\s+";
-        ESCAPE_CHAR = r"\\[\&\$]";
-        PROTECTED_SEQ = r"@#':\[;:[A-Z][A-Z0-9_]\]";
+        WORD = r"\S+";
     }
 
-    // protect escape characters.
-    // note that `\` is used as the escape character, but only for `&` and `$`.
-    // i.e. `\$` or `\&` will be protected, but `\^` will not.
-    let mut src = src.to_string();
-    for mat in ESCAPE_CHAR.find_rev(&src.clone()) {
-        let replacement = match src.chars().nth(mat.start() + 1).unwrap() {
-            '&' => "@#':[;:AS]",
-            '$' => "@#':[;:DS]",
-            _ => continue,
+    let mut layout = VvtabLayout {
+        header_rows: 0,
+        header_columns: 0,
+        align: Vec::new(),
+        footers: Vec::new(),
+        decimals: None,
+        thousands_separator: "",
+    };
+
+    let mut offset = 0;
+    while offset < src.len() {
+        let end = src[offset..].find('\n').map_or(src.len(), |pos| {
+            offset + pos + 1
+        });
+
+        let words = WORD.find_iter(&src[offset..end]).collect::<Vec<_>>();
+        let pos = |word: &regex::Match| {
+            position(src, first_line, first_column, offset + word.start())
+        };
+
+        // arguments are pointed at when invalid, and directives when missing.
+        let arg = |expected: &str| match words.as_slice() {
+            [_, arg] => *arg,
+            _ => fail(file_path, pos(&words[0]), expected),
+        };
+
+        let count = || {
+            let count = arg("expected a number");
+            count.as_str().parse().unwrap_or_else(|_| {
+                fail(file_path, pos(&count), "expected a number");
+            })
         };
 
-        src.replace_range(mat.range(), &replacement);
+        match words.first().map(|word| word.as_str()) {
+            None => {}
+            Some("#header_rows") => layout.header_rows = count(),
+            Some("#header_columns") => layout.header_columns = count(),
+            Some("#align") => {
+                for word in &words[1..] {
+                    let align = word.as_str();
+                    if !matches!(align, "left" | "right" | "center" | "numeric")
+                    {
+                        let err_msg =
+                            format!("invalid column alignment: {}", align);
+
+                        fail(file_path, pos(word), &err_msg);
+                    }
+
+                    layout.align.push(align);
+                }
+            }
+            Some("#footer") => {
+                let cells = words[1..].iter().map(|word| word.as_str());
+                layout.footers.push(cells.collect());
+            }
+            Some("#decimals") => layout.decimals = Some(count()),
+            Some("#thousands_separator") => {
+                let separator = arg("expected a separator");
+                layout.thousands_separator = separator.as_str();
+            }
+            Some(_) => break,
+        }
+
+        offset = end;
+    }
+
+    (layout, offset)
+}
+
+// which rows of a table are shown and in what order, and which of its
+// columns. columns are counted from 0, and header rows are left as they are.
+#[derive(Default)]
+pub struct RowSelection {
+    pub sort: Option<usize>,
+    pub descending: bool,
+    pub numeric: bool,
+    pub filter: Option<(usize, Regex)>,
+    pub columns: Option<Vec<usize>>,
+    pub limit: Option<usize>,
+}
+
+impl RowSelection {
+    // the items of a row in the selected columns, with `missing` in place of
+    // columns that the row does not have.
+    fn select<T: Clone>(&self, items: Vec<T>, missing: T) -> Vec<T> {
+        match self.columns {
+            Some(ref columns) => columns
+                .iter()
+                .map(|col| items.get(*col).cloned().unwrap_or(missing.clone()))
+                .collect(),
+            None => items,
+        }
     }
 
-    // extract text table information.
-    let mut lex = VvtabToken::lexer(&src);
+    fn apply(
+        &self,
+        mut rows: Vec<Vec<String>>,
+        header_rows: usize,
+    ) -> Vec<Vec<String>> {
+        // cells are compared without surrounding whitespace.
+        fn cell(row: &[String], col: usize) -> &str {
+            row.get(col).map_or("", |cell| cell.trim())
+        }
+
+        let mut body = rows.split_off(header_rows.min(rows.len()));
+        if let Some((col, ref regex)) = self.filter {
+            body.retain(|row| regex.is_match(cell(row, col)));
+        }
+
+        // in either order, cells that are not numbers come after those that
+        // are when sorting numerically.
+        let directed = |order: Ordering| match self.descending {
+            true => order.reverse(),
+            false => order,
+        };
+
+        if let Some(col) = self.sort {
+            body.sort_by(|a, b| {
+                let (a, b) = (cell(a, col), cell(b, col));
+                if !self.numeric {
+                    return directed(a.cmp(b));
+                }
+
+                match (a.parse::<f64>(), b.parse::<f64>()) {
+                    (Ok(a), Ok(b)) => {
+                        directed(a.partial_cmp(&b).unwrap_or(Ordering::Equal))
+                    }
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => directed(a.cmp(b)),
+                }
+            });
+        }
+
+        if let Some(limit) = self.limit {
+            body.truncate(limit);
+        }
+
+        rows.extend(body);
+        rows
+    }
+}
+
+// formats a number with `decimals` decimals, and `separator` between groups
+// of three digits before the decimal point.
+fn format_number(value: f64, decimals: usize, separator: &str) -> String {
+    let text = format!("{:.*}", decimals, value.abs());
+    let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+    let mut out = String::new();
+    for (i, digit) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            out += separator;
+        }
+
+        out.push(digit);
+    }
+
+    if !frac.is_empty() {
+        out = format!("{}.{}", out, frac);
+    }
+
+    // rounding may leave nothing but zeros of a negative number.
+    let is_zero = !text.chars().any(|ch| ('1'..='9').contains(&ch));
+    match value < 0.0 && !is_zero {
+        true => format!("-{}", out),
+        false => out,
+    }
+}
+
+// the footer rows of a table, computed from the rows of its body.
+fn footer_rows(layout: &VvtabLayout, body: &[Vec<String>]) -> Vec<Vec<String>> {
+    let footer_cell = |col: usize, cell: &str| {
+        let cells = body.iter().filter_map(|row| row.get(col));
+        let numbers = cells
+            .filter_map(|cell| {
+                let text = cell.trim();
+                text.parse::<f64>().ok().map(|number| (text, number))
+            })
+            .collect::<Vec<_>>();
+
+        let decimals = layout.decimals.unwrap_or_else(|| {
+            let decimals = numbers.iter().map(|(text, _)| {
+                text.split_once('.').map_or(0, |(_, frac)| frac.len())
+            });
+
+            decimals.max().unwrap_or(0)
+        });
+
+        let values = numbers.iter().map(|(_, number)| *number);
+        let (value, decimals) = match cell {
+            "sum" => (Some(values.sum()), decimals),
+            "mean" => {
+                let mean = match numbers.is_empty() {
+                    true => None,
+                    false => Some(values.sum::<f64>() / numbers.len() as f64),
+                };
+
+                (mean, layout.decimals.unwrap_or(decimals.max(2)))
+            }
+            "min" => (values.reduce(f64::min), decimals),
+            "max" => (values.reduce(f64::max), decimals),
+            "count" => return numbers.len().to_string(),
+            "-" => return String::new(),
+            text => return text.to_string(),
+        };
+
+        value.map_or(String::new(), |value| {
+            format_number(value, decimals, layout.thousands_separator)
+        })
+    };
+
+    layout
+        .footers
+        .iter()
+        .map(|footer| {
+            footer
+                .iter()
+                .enumerate()
+                .map(|(col, cell)| footer_cell(col, cell))
+                .collect()
+        })
+        .collect()
+}
+
+// the cells of the rows of a vvtab table, whose data starts at `data_start`,
+// warning about anything that looks like a mistake.
+fn vvtab_rows(
+    file_path: &str,
+    first_line: usize,
+    first_column: usize,
+    src: &str,
+    data_start: usize,
+) -> Vec<Vec<String>> {
+    let pos = |offset| position(src, first_line, first_column, offset);
+    let trailing_cell = |offset| {
+        warning!(file_path, pos(offset), "empty trailing cell");
+    };
+
+    // extract text table information, along with where each row starts.
+    let mut lex = VvtabToken::lexer(&src[data_start..]);
     let mut all_rows = Vec::new();
     let mut cur_row = Vec::new();
     let mut accum = String::new();
+    let mut row_start = None;
+    let mut last_item = 0;
 
     while let Some(tok) = lex.next() {
+        let offset = data_start + lex.span().start;
+        if row_start.is_none() && !lex.slice().trim().is_empty() {
+            row_start = Some(offset);
+        }
+
         match tok {
             VvtabToken::NextItem => {
                 cur_row.push(accum);
                 accum = String::new();
+                last_item = offset;
             }
             VvtabToken::NextRow => {
                 // a trailing `&` is not necessary for the last item in a row.
                 if accum.len() > 0 {
+                    if accum.trim().is_empty() && !cur_row.is_empty() {
+                        trailing_cell(last_item);
+                    }
+
                     cur_row.push(accum);
                     accum = String::new();
                 }
 
-                all_rows.push(cur_row);
+                all_rows.push((cur_row, row_start.take().unwrap_or(offset)));
                 cur_row = Vec::new();
             }
-            _ => accum += lex.slice(),
+            VvtabToken::Escape => accum += &lex.slice()[1..],
+
+            // a user should never encode protected sequences manually, but
+            // those of inline tables were protected by the preprocessor.
+            VvtabToken::ProtectedSeq => match &lex.slice()[7..9] {
+                "LB" | "RB" | "EC" | "P_" | "A_" => accum += lex.slice(),
+                prot_code => {
+                    let warn_msg = format!(
+                        "unknown protected sequence {} is removed",
+                        prot_code,
+                    );
+
+                    warning!(file_path, pos(offset), warn_msg);
+                }
+            },
+            VvtabToken::Error => accum += lex.slice(),
         }
     }
 
     // a trailing `$` should not be necessary for the last row, nor should a
-    // trailing `&` be necessary for the last item.
-    if accum.len() > 0 {
-        cur_row.push(accum);
+    // trailing `&` be necessary for the last item. a line break after the
+    // last `$` does not start another row.
+    if !accum.trim().is_empty() || !cur_row.is_empty() {
+        if accum.len() > 0 {
+            if accum.trim().is_empty() && !cur_row.is_empty() {
+                trailing_cell(last_item);
+            }
+
+            cur_row.push(accum);
+        }
+
+        all_rows.push((cur_row, row_start.unwrap_or(src.len())));
     }
 
-    if cur_row.len() > 0 {
-        all_rows.push(cur_row);
+    // every row should have as many cells as the first one.
+    if let Some((first_row, _)) = all_rows.first() {
+        for (row, start) in &all_rows[1..] {
+            if row.len() != first_row.len() {
+                let warn_msg = format!(
+                    "row has a different number of cells than the first \
+                     row ({} instead of {})",
+                    row.len(),
+                    first_row.len(),
+                );
+
+                warning!(file_path, pos(*start), warn_msg);
+            }
+        }
+    }
+
+    all_rows.into_iter().map(|(row, _)| row).collect()
+}
+
+// converts a text table to vvsml. `first_line` and `first_column` are where
+// `src` starts in `file_path`, as a table may be written inline in a vvsml
+// file.
+pub fn vvtab_to_vvsml(
+    file_path: &str,
+    first_line: usize,
+    first_column: usize,
+    src: &str,
+    selection: &RowSelection,
+) -> String {
+    lazy_regex! {
+        WHITESPACE = r"\s+";
     }
 
+    let (layout, data_start) =
+        vvtab_layout(file_path, first_line, first_column, src);
+    let all_rows =
+        vvtab_rows(file_path, first_line, first_column, src, data_start);
+    let mut all_rows = selection.apply(all_rows, layout.header_rows);
+    let body = &all_rows[layout.header_rows.min(all_rows.len())..];
+    let footers = footer_rows(&layout, body);
+    all_rows.extend(footers);
+
+    // header columns are counted in the table as written, and then follow
+    // their columns through the selection.
+    let width = all_rows.iter().map(Vec::len).max().unwrap_or(0);
+    let is_header = (0..width).map(|col| col < layout.header_columns);
+    let is_header = selection.select(is_header.collect(), false);
+
+    let all_rows = all_rows
+        .into_iter()
+        .map(|row| selection.select(row, String::new()));
+
+    let align = match layout.align.is_empty() {
+        true => layout.align,
+        false => selection.select(layout.align, "left"),
+    };
+
     // convert extracted information to vvsml.
-    let mut out = String::from("table{");
-    for row in all_rows {
-        out += "row{";
-        for item in row {
-            out += &format!("text{{{}}}", item);
+    let mut out = String::from("table");
+    if !align.is_empty() {
+        out += &format!("{{align={}}}", align.join(","));
+    }
+
+    out += "{";
+    for (i, row) in all_rows.into_iter().enumerate() {
+        out += match i < layout.header_rows {
+            true => "header_row{",
+            false => "row{",
+        };
+
+        for (j, item) in row.into_iter().enumerate() {
+            out += &match is_header.get(j).copied().unwrap_or(false) {
+                true => format!("cell{{header}}{{text{{{}}}}}", item),
+                false => format!("text{{{}}}", item),
+            };
         }
 
         out += "}";
     }
     
     out += "}";
+    WHITESPACE.replace_all(&out, " ").to_string()
+}
+
+// protects the characters in data that would otherwise be read as vvsml, so
+// that it ends up in the document exactly as it was.
+fn protect_data(text: &str) -> String {
+    text.chars().fold(String::new(), |mut out, ch| {
+        match ch {
+            '{' => out += "@#':[;:LB]",
+            '}' => out += "@#':[;:RB]",
+            '.' => out += "@#':[;:P_]",
+            '@' => out += "@#':[;:A_]",
+            _ => out.push(ch),
+        }
+
+        out
+    })
+}
+
+// parses delimiter separated values as described by rfc 4180, where fields
+// may be quoted to contain delimiters, line breaks and `""` for quotes.
+fn parse_dsv(file_path: &str, src: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut line = 1;
+    let mut chars = src.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if field.is_empty() => {
+                let start_line = line;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(ch) => {
+                            if ch == '\n' {
+                                line += 1;
+                            }
 
-    // deprotect protected sequences.
-    // note that invalid vvtab sequences are simply removed.
-    for mat in PROTECTED_SEQ.find_rev(&out.clone()) {
-        let prot_code = out[(mat.start() + 7)..(mat.start() + 9)].to_string();
-        let replacement = match prot_code.as_ref() {
-            "AS" => "&",
-            "DS" => "$",
-            _ => "",
+                            field.push(ch);
+                        }
+                        None => {
+                            error!(file_path, start_line, "unterminated quote");
+                            process::exit(-1);
+                        }
+                    }
+                }
+
+                let next = chars.peek().copied();
+                if !matches!(next, None | Some('\r' | '\n'))
+                    && next != Some(delimiter)
+                {
+                    let err_msg = "unexpected character after quoted field";
+                    error!(file_path, line, err_msg);
+                    process::exit(-1);
+                }
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(field);
+                rows.push(row);
+                row = Vec::new();
+                field = String::new();
+                line += 1;
+            }
+            ch if ch == delimiter => row.push(std::mem::take(&mut field)),
+            ch => field.push(ch),
+        }
+    }
+
+    // a line break after the last row does not start another one.
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+// converts csv, or tsv with `delimiter` set to a tab, to a vvsml table. with
+// `header`, the first row is a header row.
+pub fn dsv_to_vvsml(
+    file_path: &str,
+    src: &str,
+    delimiter: char,
+    header: bool,
+) -> String {
+    data_to_vvsml(&parse_dsv(file_path, src, delimiter), header)
+}
+
+// a table of data fields, with the first row as a header row if `header` is
+// set.
+fn data_to_vvsml(rows: &[Vec<String>], header: bool) -> String {
+    let mut out = String::from("table{");
+    for (i, row) in rows.iter().enumerate() {
+        out += match header && i == 0 {
+            true => "header_row{",
+            false => "row{",
         };
 
-        out.replace_range(mat.range(), &replacement);
+        for field in row {
+            out += &format!("text{{{}}}", protect_data(field));
+        }
+
+        out += "}";
     }
 
-    WHITESPACE.replace_all(&out, " ").to_string()
+    out + "}"
+}
+
+// the rows of a vvtab, csv or tsv file, told apart by the extension of
+// `file_path`, and the names of its columns. the last header row of a vvtab
+// file names its columns, as does the first row of any file with `header`.
+// the cells of vvtab files are vvsml, while those of other files are data.
+pub fn data_rows(
+    file_path: &str,
+    src: &str,
+    header: bool,
+) -> (Vec<String>, Vec<Vec<String>>) {
+    let extension = file_path.rsplit_once('.').map_or("", |(_, ext)| ext);
+    let (mut rows, header_rows, is_data) = match extension {
+        "csv" => (parse_dsv(file_path, src, ','), header as usize, true),
+        "tsv" => (parse_dsv(file_path, src, '\t'), header as usize, true),
+        _ => {
+            let (layout, data_start) = vvtab_layout(file_path, 1, 1, src);
+            let rows = vvtab_rows(file_path, 1, 1, src, data_start);
+            (rows, layout.header_rows.max(header as usize), false)
+        }
+    };
+
+    let body = rows
+        .split_off(header_rows.min(rows.len()))
+        .into_iter()
+        .map(|row| {
+            let cells = row.iter().map(|cell| match is_data {
+                true => protect_data(cell),
+                false => cell.trim().to_string(),
+            });
+
+            cells.collect()
+        })
+        .collect();
+
+    let names = rows.pop().unwrap_or_default();
+    let names = names.iter().map(|name| name.trim().to_string()).collect();
+    (names, body)
+}
+
+// converts a json array of objects to a vvsml table, with a header row of
+// `keys` and a column for each of them.
+pub fn json_to_vvsml(file_path: &str, src: &str, keys: &[&str]) -> String {
+    let value = serde_json::from_str(src).unwrap_or_else(|e| {
+        // the position is given like that of other errors instead.
+        let pos = format!("{}:{}", e.line(), e.column());
+        let err_msg = e.to_string();
+        let err_msg = err_msg
+            .rsplit_once(" at line ")
+            .map_or(err_msg.as_str(), |(err_msg, _)| err_msg);
+        fail(file_path, pos, &format!("invalid json: {}", err_msg));
+    });
+
+    let objects = match value {
+        serde_json::Value::Array(objects) => objects,
+        _ => {
+            error!(file_path, "expected an array of objects");
+            process::exit(-1);
+        }
+    };
+
+    let mut rows = vec![keys.iter().map(|key| key.to_string()).collect()];
+    for (i, object) in objects.iter().enumerate() {
+        let object = object.as_object().unwrap_or_else(|| {
+            let err_msg = format!("element {} is not an object", i + 1);
+            error!(file_path, err_msg);
+            process::exit(-1);
+        });
+
+        // strings are written without quotes, and other values as json.
+        let row = keys.iter().map(|key| match object.get(*key) {
+            Some(serde_json::Value::String(text)) => text.clone(),
+            Some(serde_json::Value::Null) => String::new(),
+            Some(value) => value.to_string(),
+            None => {
+                let err_msg = format!("element {} has no key {}", i + 1, key);
+                error!(file_path, err_msg);
+                process::exit(-1);
+            }
+        });
+
+        rows.push(row.collect());
+    }
+
+    data_to_vvsml(&rows, true)
 }